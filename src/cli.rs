@@ -1,18 +1,95 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "rust-wg", version, about = "WireGuard container runtime")]
 pub struct Cli {
+    /// Config file [default: $WG_CONFIG, then /etc/wg/wg.toml]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Directory for keys, peer configs and state [default: $WG_STATE_DIR,
+    /// then `[paths] state_dir`, then /var/lib/wg]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub state_dir: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Run,
+    Run {
+        /// Print what would be written, allocated and set up, and exit.
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the dry-run plan as JSON.
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
     ShowPeer {
+        /// Peer IDs (`peer-<slug>`) or names from the `names` list.
         #[arg(required = true)]
         peers: Vec<String>,
+        /// Interface the peers belong to, when several are configured.
+        #[arg(long)]
+        interface: Option<String>,
+        /// Print only client.conf, without the QR code.
+        #[arg(long, conflicts_with = "qr_only")]
+        conf_only: bool,
+        /// Print only the QR code, without client.conf.
+        #[arg(long)]
+        qr_only: bool,
+    },
+    Generate {
+        /// Print what would be written and allocated, and exit.
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the dry-run plan as JSON.
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
+    /// Show live handshakes and transfer counters of each interface's peers.
+    Status {
+        /// Print JSON instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove interfaces and nftables tables left behind by `run`; stop it first.
+    Cleanup,
+    /// Check privileges, sysctls, routes, ports and config for `run`.
+    Doctor,
+    Peer {
+        #[command(subcommand)]
+        command: PeerCommands,
+    },
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PeerCommands {
+    /// Remove a peer from the interface and retire its keys for good.
+    Revoke {
+        /// Peer ID (`peer-<slug>`) or name from the `names` list.
+        peer: String,
+        /// Interface the peer belongs to, when several are configured.
+        #[arg(long)]
+        interface: Option<String>,
+        /// Overwrite and delete the key material instead of archiving it.
+        #[arg(long)]
+        delete: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Report every problem with the config file and WG_* overrides at once.
+    Check,
+    /// Print the effective configuration with the source of every value.
+    Show {
+        /// Print JSON instead of annotated TOML.
+        #[arg(long)]
+        json: bool,
     },
-    Generate,
 }
@@ -1,6 +1,6 @@
+use crate::config::files::Files;
 use crate::config::types::{Paths, Peer};
-use anyhow::{Context, Result};
-use std::fs;
+use anyhow::Result;
 
 pub(super) fn assets_missing(paths: &Paths, peers: &[Peer]) -> bool {
     if !paths.keys.join("server.key").exists() || !paths.keys.join("server.pub").exists() {
@@ -22,11 +22,12 @@ pub(super) fn assets_missing(paths: &Paths, peers: &[Peer]) -> bool {
     false
 }
 
-pub(super) fn ensure_dirs(paths: &Paths) -> Result<()> {
-    fs::create_dir_all(&paths.root).context("creating root dir")?;
-    fs::create_dir_all(&paths.keys).context("creating keys dir")?;
-    fs::create_dir_all(&paths.peers).context("creating peers dir")?;
-    fs::create_dir_all(&paths.server).context("creating server dir")?;
-    fs::create_dir_all(&paths.state).context("creating state dir")?;
+pub(super) fn ensure_dirs(paths: &Paths, files: &mut Files) -> Result<()> {
+    files.create_dir(&paths.root)?;
+    files.create_dir(&paths.keys)?;
+    files.create_dir(&paths.peers)?;
+    files.create_dir(&paths.server)?;
+    files.create_dir(&paths.state)?;
+    files.create_dir(&paths.revoked)?;
     Ok(())
 }
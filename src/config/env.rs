@@ -1,4 +1,78 @@
-use crate::config::types::ConfigFile;
+use crate::config::types::{ConfigFile, NatMode, Policy};
+use crate::config::validate::{self, Kind};
+
+/// Every override with the key it replaces and what it holds; lists are
+/// comma-separated.
+const OVERRIDES: &[(&str, &str, Kind)] = &[
+    ("WG_LISTEN_PORT", "server.listen_port", Kind::U16),
+    ("WG_EXTERNAL_ADDRESS", "server.external_address", Kind::Str),
+    ("WG_SUBNET_V4", "network.subnet_v4", Kind::Net4),
+    ("WG_SUBNET_V6", "network.subnet_v6", Kind::Net6),
+    (
+        "WG_ALLOWED_IPS",
+        "network.allowed_ips",
+        Kind::List(&Kind::Net),
+    ),
+    ("WG_PEER_DNS", "network.peer_dns", Kind::List(&Kind::Ip)),
+    (
+        "WG_ASSIGN_SUBNET_PREFIX",
+        "network.assign_subnet_prefix",
+        Kind::Bool,
+    ),
+    ("WG_PEER_TO_PEER", "network.peer_to_peer", validate::POLICY),
+    (
+        "WG_EGRESS_INTERFACES",
+        "network.egress_interfaces",
+        Kind::List(&Kind::Str),
+    ),
+    ("WG_NAT", "network.nat", validate::NAT),
+    ("WG_NAT_V6", "network.nat_v6", validate::NAT),
+    ("WG_NPT_PREFIX_V6", "network.npt_prefix_v6", Kind::Net6),
+    ("WG_SNAT_V4", "network.snat_v4", Kind::Ipv4),
+    ("WG_SNAT_V6", "network.snat_v6", Kind::Ipv6),
+    ("WG_MTU", "network.mtu", Kind::U16),
+    ("WG_PEER_COUNT", "peers.count", Kind::Usize),
+    ("WG_PEER_NAMES", "peers.names", Kind::List(&Kind::Str)),
+    ("WG_EMIT_QR", "runtime.emit_qr", Kind::Bool),
+    ("WG_STATE_DIR", "paths.state_dir", Kind::Str),
+    ("WG_METRICS_LISTEN", "metrics.listen", Kind::Listen),
+    ("WG_EVENTS_WEBHOOK", "events.webhook", Kind::Str),
+];
+
+/// The overrides that are set but would not parse, with what is wrong.
+pub(super) fn invalid_overrides() -> Vec<(&'static str, String)> {
+    let mut out = Vec::new();
+    for (var, _, kind) in OVERRIDES {
+        let Some(value) = env_string(var) else {
+            continue;
+        };
+        let result = match kind {
+            Kind::List(item) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .try_for_each(|value| validate::check_str(**item, value)),
+            _ => validate::check_str(*kind, &value),
+        };
+        if let Err(message) = result {
+            out.push((*var, message));
+        }
+    }
+    out
+}
+
+/// The keys `apply_env_overrides` replaces, as dotted paths, with the
+/// variable each comes from.
+pub(super) fn applied_overrides() -> Vec<(&'static str, &'static str)> {
+    OVERRIDES
+        .iter()
+        .filter(|(var, _, _)| match *var {
+            "WG_PEER_NAMES" => env_list(var).is_some_and(|names| !names.is_empty()),
+            _ => env_string(var).is_some(),
+        })
+        .map(|(var, key, _)| (*key, *var))
+        .collect()
+}
 
 pub(super) fn apply_env_overrides(cfg: &mut ConfigFile) {
     if let Some(port) = env_u16("WG_LISTEN_PORT") {
@@ -19,6 +93,35 @@ pub(super) fn apply_env_overrides(cfg: &mut ConfigFile) {
     if let Some(list) = env_list("WG_PEER_DNS") {
         cfg.network.peer_dns = list;
     }
+    if let Some(value) = env_bool("WG_ASSIGN_SUBNET_PREFIX") {
+        cfg.network.assign_subnet_prefix = value;
+    }
+    match env_string("WG_PEER_TO_PEER").as_deref() {
+        Some("allow") => cfg.network.peer_to_peer = Policy::Allow,
+        Some("deny") => cfg.network.peer_to_peer = Policy::Deny,
+        _ => {}
+    }
+    if let Some(list) = env_list("WG_EGRESS_INTERFACES") {
+        cfg.network.egress_interfaces = Some(list);
+    }
+    if let Some(mode) = env_nat("WG_NAT") {
+        cfg.network.nat = mode;
+    }
+    if let Some(mode) = env_nat("WG_NAT_V6") {
+        cfg.network.nat_v6 = Some(mode);
+    }
+    if let Some(prefix) = env_string("WG_NPT_PREFIX_V6") {
+        cfg.network.npt_prefix_v6 = Some(prefix);
+    }
+    if let Some(addr) = env_string("WG_SNAT_V4") {
+        cfg.network.snat_v4 = Some(addr);
+    }
+    if let Some(addr) = env_string("WG_SNAT_V6") {
+        cfg.network.snat_v6 = Some(addr);
+    }
+    if let Some(mtu) = env_u16("WG_MTU") {
+        cfg.network.mtu = Some(mtu);
+    }
     if let Some(count) = env_usize("WG_PEER_COUNT") {
         cfg.peers.count = Some(count);
     }
@@ -30,6 +133,15 @@ pub(super) fn apply_env_overrides(cfg: &mut ConfigFile) {
     if let Some(value) = env_bool("WG_EMIT_QR") {
         cfg.runtime.emit_qr = value;
     }
+    if let Some(dir) = env_string("WG_STATE_DIR") {
+        cfg.paths.state_dir = Some(dir.into());
+    }
+    if let Some(addr) = env_string("WG_METRICS_LISTEN") {
+        cfg.metrics.listen = Some(addr);
+    }
+    if let Some(url) = env_string("WG_EVENTS_WEBHOOK") {
+        cfg.events.webhook = Some(url);
+    }
 }
 
 fn env_string(key: &str) -> Option<String> {
@@ -53,6 +165,15 @@ fn env_list(key: &str) -> Option<Vec<String>> {
     })
 }
 
+fn env_nat(key: &str) -> Option<NatMode> {
+    match env_string(key)?.as_str() {
+        "masquerade" => Some(NatMode::Masquerade),
+        "routed" => Some(NatMode::Routed),
+        "npt" => Some(NatMode::Npt),
+        _ => None,
+    }
+}
+
 fn env_bool(key: &str) -> Option<bool> {
     env_string(key).and_then(|val| match val.to_ascii_lowercase().as_str() {
         "true" | "1" | "yes" => Some(true),
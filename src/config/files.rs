@@ -0,0 +1,264 @@
+use crate::config::io::{read_to_string, write_atomic, write_secret};
+use crate::config::qr::{print_qr, write_qr_png};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lines of unchanged context kept around each change in a diff.
+const CONTEXT: usize = 2;
+
+/// Where generation writes: straight to disk, or, for a dry run, into memory
+/// so that later reads in the same run see what would have been written.
+pub(super) struct Files {
+    /// `None` when writing to disk.
+    staged: Option<Vec<Staged>>,
+}
+
+struct Staged {
+    path: PathBuf,
+    kind: Kind,
+    existed: bool,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Dir,
+    Text,
+    Secret,
+    Binary,
+}
+
+/// One file or directory a dry run would create or change.
+#[derive(Debug, Serialize)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    /// `create`, `modify` or `mkdir`.
+    pub action: &'static str,
+    /// Key material, whose content is never shown.
+    pub secret: bool,
+    /// Line diff against the current content, with key values redacted;
+    /// `None` for key material, directories and binary files.
+    pub diff: Option<String>,
+}
+
+impl Files {
+    pub(super) fn disk() -> Self {
+        Self { staged: None }
+    }
+
+    pub(super) fn dry_run() -> Self {
+        Self {
+            staged: Some(Vec::new()),
+        }
+    }
+
+    pub(super) fn create_dir(&mut self, path: &Path) -> Result<()> {
+        match self.staged {
+            None => {
+                fs::create_dir_all(path).with_context(|| format!("creating {}", path.display()))
+            }
+            Some(_) if self.exists(path) => Ok(()),
+            Some(_) => self.stage(path, Kind::Dir, None),
+        }
+    }
+
+    pub(super) fn write(&mut self, path: &Path, data: &str) -> Result<()> {
+        match self.staged {
+            None => write_atomic(path, data.as_bytes()),
+            Some(_) => self.stage(path, Kind::Text, Some(data.to_string())),
+        }
+    }
+
+    pub(super) fn write_secret(&mut self, path: &Path, data: &str) -> Result<()> {
+        match self.staged {
+            None => write_secret(path, data),
+            Some(_) => self.stage(path, Kind::Secret, Some(data.to_string())),
+        }
+    }
+
+    /// Prints the QR code of `conf` and writes it next to it as `png`.
+    pub(super) fn emit_qr(&mut self, conf: &Path, png: PathBuf) -> Result<()> {
+        match self.staged {
+            None => {
+                print_qr(conf)?;
+                write_qr_png(conf, png)
+            }
+            Some(_) => self.stage(&png, Kind::Binary, None),
+        }
+    }
+
+    pub(super) fn read_to_string(&self, path: &Path) -> Result<String> {
+        match self.find(path) {
+            Some(Staged {
+                after: Some(data), ..
+            }) => Ok(data.clone()),
+            _ => read_to_string(path),
+        }
+    }
+
+    pub(super) fn exists(&self, path: &Path) -> bool {
+        self.find(path).is_some() || path.exists()
+    }
+
+    /// The staged changes, leaving out files rewritten with the same content.
+    /// Keys made up for the dry run read as `(new key)` where they show up.
+    pub(super) fn into_plan(self) -> Vec<PlannedFile> {
+        let staged = self.staged.unwrap_or_default();
+        let new_keys: Vec<&str> = staged
+            .iter()
+            .filter(|file| file.kind == Kind::Secret && !file.existed)
+            .filter_map(|file| Some(file.after.as_deref()?.trim()))
+            .collect();
+        let mut out = Vec::new();
+        for file in &staged {
+            if file.kind == Kind::Text && file.before == file.after {
+                continue;
+            }
+            let action = match (file.kind, file.existed) {
+                (Kind::Dir, _) => "mkdir",
+                (_, false) => "create",
+                (_, true) => "modify",
+            };
+            let diff = match (file.kind, &file.after) {
+                (Kind::Text, Some(after)) => {
+                    let after = new_keys
+                        .iter()
+                        .fold(after.clone(), |text, key| text.replace(key, "(new key)"));
+                    Some(diff_lines(
+                        &redact(file.before.as_deref().unwrap_or_default()),
+                        &redact(&after),
+                    ))
+                }
+                _ => None,
+            };
+            out.push(PlannedFile {
+                path: file.path.clone(),
+                action,
+                secret: file.kind == Kind::Secret,
+                diff,
+            });
+        }
+        out
+    }
+
+    fn find(&self, path: &Path) -> Option<&Staged> {
+        self.staged.as_ref()?.iter().find(|file| file.path == path)
+    }
+
+    fn stage(&mut self, path: &Path, kind: Kind, after: Option<String>) -> Result<()> {
+        let staged = self.staged.get_or_insert_with(Vec::new);
+        if let Some(file) = staged.iter_mut().find(|file| file.path == path) {
+            file.after = after;
+            return Ok(());
+        }
+        let existed = path.exists();
+        let before = if existed && kind == Kind::Text {
+            Some(read_to_string(path)?)
+        } else {
+            None
+        };
+        staged.push(Staged {
+            path: path.to_path_buf(),
+            kind,
+            existed,
+            before,
+            after,
+        });
+        Ok(())
+    }
+}
+
+/// Hides the key values that `client.conf` and `server.conf` carry.
+fn redact(text: &str) -> String {
+    text.lines()
+        .map(|line| match line.split_once(" = ") {
+            Some((key @ ("PrivateKey" | "PresharedKey"), _)) => format!("{key} = (redacted)\n"),
+            _ => format!("{line}\n"),
+        })
+        .collect()
+}
+
+/// A line diff of `before` and `after`, marking lines with `-`, `+` or a
+/// space and eliding unchanged runs far from any change as `...`.
+fn diff_lines(before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+
+    // Longest common subsequence lengths of every pair of suffixes.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&n| lines[n].0 != ' ').collect();
+    let near_change = |n: usize| {
+        changed
+            .iter()
+            .any(|&c| n + CONTEXT >= c && n <= c + CONTEXT)
+    };
+    let mut out = String::new();
+    let mut elided = false;
+    for (n, (mark, line)) in lines.iter().enumerate() {
+        if *mark == ' ' && !near_change(n) {
+            if !elided {
+                out.push_str("  ...\n");
+                elided = true;
+            }
+            continue;
+        }
+        elided = false;
+        out.push_str(format!("{mark} {line}").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_redacted_lines_with_context() {
+        let before = "[Interface]\nAddress = 10.0.0.1/32\nListenPort = 51820\n\
+                      PrivateKey = a\n\n[Peer]\nPublicKey = x\n";
+        let after = "[Interface]\nAddress = 10.0.0.1/32\nListenPort = 51821\n\
+                     PrivateKey = b\n\n[Peer]\nPublicKey = x\n";
+        assert_eq!(
+            diff_lines(&redact(before), &redact(after)),
+            concat!(
+                "  [Interface]\n",
+                "  Address = 10.0.0.1/32\n",
+                "- ListenPort = 51820\n",
+                "+ ListenPort = 51821\n",
+                "  PrivateKey = (redacted)\n",
+                "\n",
+                "  ...\n",
+            )
+        );
+        assert_eq!(diff_lines("", "a\nb\n"), "+ a\n+ b\n");
+    }
+}
@@ -1,70 +1,8 @@
+use crate::config::types::Peer;
 use anyhow::{Context, Result};
+use ipnet::{Ipv4Net, Ipv6Net};
 use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
-
-pub(super) fn gather_assigned_ips(peers_root: &Path) -> Result<HashSet<String>> {
-    let mut used = HashSet::new();
-    if !peers_root.exists() {
-        return Ok(used);
-    }
-    for entry in fs::read_dir(peers_root).context("reading peers dir")? {
-        let entry = entry?;
-        if !entry.file_type()?.is_dir() {
-            continue;
-        }
-        let conf = entry.path().join("client.conf");
-        if conf.exists() {
-            let text = fs::read_to_string(conf).context("reading client.conf")?;
-            for addr in extract_addresses(&text) {
-                if addr.contains('.') {
-                    used.insert(addr);
-                }
-            }
-        }
-    }
-    Ok(used)
-}
-
-pub(super) fn gather_assigned_ips_v6(peers_root: &Path) -> Result<HashSet<String>> {
-    let mut used = HashSet::new();
-    if !peers_root.exists() {
-        return Ok(used);
-    }
-    for entry in fs::read_dir(peers_root).context("reading peers dir")? {
-        let entry = entry?;
-        if !entry.file_type()?.is_dir() {
-            continue;
-        }
-        let conf = entry.path().join("client.conf");
-        if conf.exists() {
-            let text = fs::read_to_string(conf).context("reading client.conf")?;
-            for addr in extract_addresses(&text) {
-                if addr.contains(':') {
-                    used.insert(addr);
-                }
-            }
-        }
-    }
-    Ok(used)
-}
-
-pub(super) fn extract_addresses(text: &str) -> Vec<String> {
-    for line in text.lines() {
-        let line = line.trim();
-        if let Some(rest) = line.strip_prefix("Address") {
-            let parts: Vec<&str> = rest.split('=').collect();
-            if parts.len() == 2 {
-                return parts[1]
-                    .split(',')
-                    .map(|item| item.trim().to_string())
-                    .filter(|item| !item.is_empty())
-                    .collect();
-            }
-        }
-    }
-    Vec::new()
-}
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 pub(super) fn next_available_v4(
     hosts: &mut ipnet::Ipv4AddrRange,
@@ -91,3 +29,52 @@ pub(super) fn next_available_v6(
     }
     anyhow::bail!("no available IPv6 addresses in subnet")
 }
+
+/// Parses each peer's `address_v4`/`address_v6`, checking that it lies in the
+/// configured subnet and is not claimed by another peer.
+pub(super) fn static_addresses(
+    peers: &[Peer],
+    v4_net: &Ipv4Net,
+    v6_net: Option<&Ipv6Net>,
+) -> Result<Vec<(Option<Ipv4Addr>, Option<Ipv6Addr>)>> {
+    let mut seen_v4 = HashSet::new();
+    let mut seen_v6 = HashSet::new();
+    let mut out = Vec::new();
+    for peer in peers {
+        let v4 = match peer.settings.address_v4.as_deref() {
+            Some(value) => {
+                let addr: Ipv4Addr = value
+                    .parse()
+                    .with_context(|| format!("parsing address_v4 for {}", peer.id))?;
+                if !v4_net.contains(&addr) {
+                    anyhow::bail!("address_v4 {addr} for {} is outside {v4_net}", peer.id);
+                }
+                if !seen_v4.insert(addr) {
+                    anyhow::bail!("address_v4 {addr} is assigned to more than one peer");
+                }
+                Some(addr)
+            }
+            None => None,
+        };
+        let v6 = match peer.settings.address_v6.as_deref() {
+            Some(value) => {
+                let net = v6_net.with_context(|| {
+                    format!("address_v6 for {} requires subnet_v6 to be set", peer.id)
+                })?;
+                let addr: Ipv6Addr = value
+                    .parse()
+                    .with_context(|| format!("parsing address_v6 for {}", peer.id))?;
+                if !net.contains(&addr) {
+                    anyhow::bail!("address_v6 {addr} for {} is outside {net}", peer.id);
+                }
+                if !seen_v6.insert(addr) {
+                    anyhow::bail!("address_v6 {addr} is assigned to more than one peer");
+                }
+                Some(addr)
+            }
+            None => None,
+        };
+        out.push((v4, v6));
+    }
+    Ok(out)
+}
@@ -1,49 +1,141 @@
-use crate::config::io::{read_to_string, run_output, run_output_with_stdin, write_secret};
+use crate::config::files::Files;
 use crate::config::types::{KeyPair, Paths};
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use std::path::Path;
+use x25519_dalek::{PublicKey, StaticSecret};
 
-pub(super) fn ensure_server_keys(paths: &Paths) -> Result<KeyPair> {
+const KEY_LEN: usize = 32;
+
+pub(super) fn ensure_server_keys(paths: &Paths, files: &mut Files) -> Result<KeyPair> {
     let private_path = paths.keys.join("server.key");
     let public_path = paths.keys.join("server.pub");
-    if private_path.exists() && public_path.exists() {
+    if files.exists(&private_path) && files.exists(&public_path) {
         return Ok(KeyPair {
-            private: read_to_string(private_path)?,
-            public: read_to_string(public_path)?,
+            private: files.read_to_string(&private_path)?,
+            public: files.read_to_string(&public_path)?,
         });
     }
-    let private = run_output("wg", &["genkey"])?;
-    write_secret(&private_path, &private)?;
-    let public = run_output_with_stdin("wg", &["pubkey"], &private)?;
-    write_secret(&public_path, &public)?;
+    let private = generate_private_key()?;
+    files.write_secret(&private_path, &private)?;
+    let public = public_key(&private)?;
+    files.write_secret(&public_path, &public)?;
     Ok(KeyPair { private, public })
 }
 
-pub(super) fn ensure_peer_keys(peer_dir: &Path) -> Result<KeyPair> {
+pub(super) fn ensure_peer_keys(peer_dir: &Path, files: &mut Files) -> Result<KeyPair> {
     let private_path = peer_dir.join("private.key");
     let public_path = peer_dir.join("public.key");
     let psk_path = peer_dir.join("preshared.key");
 
-    let private = if private_path.exists() {
-        read_to_string(&private_path)?
+    let private = if files.exists(&private_path) {
+        files.read_to_string(&private_path)?
     } else {
-        let key = run_output("wg", &["genkey"])?;
-        write_secret(&private_path, &key)?;
+        let key = generate_private_key()?;
+        files.write_secret(&private_path, &key)?;
         key
     };
 
-    let public = if public_path.exists() {
-        read_to_string(&public_path)?
+    let public = if files.exists(&public_path) {
+        files.read_to_string(&public_path)?
     } else {
-        let key = run_output_with_stdin("wg", &["pubkey"], &private)?;
-        write_secret(&public_path, &key)?;
+        let key = public_key(&private)?;
+        files.write_secret(&public_path, &key)?;
         key
     };
 
-    if !psk_path.exists() {
-        let psk = run_output("wg", &["genpsk"])?;
-        write_secret(&psk_path, &psk)?;
+    if !files.exists(&psk_path) {
+        let psk = generate_preshared_key()?;
+        files.write_secret(&psk_path, &psk)?;
     }
 
     Ok(KeyPair { private, public })
 }
+
+/// Equivalent of `wg genkey`: a clamped Curve25519 scalar from the OS CSPRNG.
+fn generate_private_key() -> Result<String> {
+    let mut bytes = random_bytes()?;
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Ok(STANDARD.encode(bytes))
+}
+
+/// Equivalent of `wg pubkey`.
+fn public_key(private: &str) -> Result<String> {
+    let secret = StaticSecret::from(decode_key(private)?);
+    Ok(STANDARD.encode(PublicKey::from(&secret).as_bytes()))
+}
+
+/// Equivalent of `wg genpsk`.
+fn generate_preshared_key() -> Result<String> {
+    Ok(STANDARD.encode(random_bytes()?))
+}
+
+fn random_bytes() -> Result<[u8; KEY_LEN]> {
+    let mut bytes = [0u8; KEY_LEN];
+    getrandom::fill(&mut bytes)
+        .map_err(|err| anyhow::anyhow!("reading OS random source: {err}"))?;
+    Ok(bytes)
+}
+
+pub fn decode_key(key: &str) -> Result<[u8; KEY_LEN]> {
+    let bytes = STANDARD.decode(key.trim()).context("decoding base64 key")?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        anyhow::anyhow!("key is {} bytes, expected {KEY_LEN}", bytes.len())
+    })
+}
+
+pub fn encode_key(key: &[u8; KEY_LEN]) -> String {
+    STANDARD.encode(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_key_matches_wg_pubkey() {
+        assert_eq!(
+            public_key("yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=").unwrap(),
+            "HIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw="
+        );
+    }
+
+    #[test]
+    fn public_key_matches_rfc7748_vectors() {
+        assert_eq!(
+            public_key("dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=").unwrap(),
+            "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo="
+        );
+        assert_eq!(
+            public_key("XasIfmJKikt54X+Lg4AO5m87sSkmGLb9HC+LJ/+I4Os=\n").unwrap(),
+            "3p7bfXt9wbTTW2HC7OQ1Nz+DQ8hbeGdNrfx+FG+IK08="
+        );
+    }
+
+    #[test]
+    fn generated_private_key_is_clamped() {
+        let key = generate_private_key().unwrap();
+        assert_eq!(key.len(), 44);
+        let bytes = decode_key(&key).unwrap();
+        assert_eq!(bytes[0] & 7, 0);
+        assert_eq!(bytes[31] & 128, 0);
+        assert_eq!(bytes[31] & 64, 64);
+    }
+
+    #[test]
+    fn generated_keys_are_distinct() {
+        let a = generate_preshared_key().unwrap();
+        let b = generate_preshared_key().unwrap();
+        assert_eq!(decode_key(&a).unwrap().len(), KEY_LEN);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(public_key("not base64!").is_err());
+        assert!(public_key("AAAA").is_err());
+    }
+}
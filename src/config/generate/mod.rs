@@ -1,15 +1,28 @@
+use crate::config::files::Files;
+use crate::config::io::now_secs;
+use crate::config::leases::{self, Lease};
 use crate::config::types::{ConfigFile, Paths, Peer};
 use anyhow::{Context, Result};
 use ipnet::{Ipv4Net, Ipv6Net};
-use std::fs;
+use std::collections::HashSet;
 
 mod ip_alloc;
 mod keys;
 mod peer_conf;
 mod server_conf;
 
-pub(super) fn generate_all(cfg: &ConfigFile, peers: &[Peer], paths: &Paths) -> Result<()> {
-    let server_keys = keys::ensure_server_keys(paths)?;
+pub use keys::{decode_key, encode_key};
+
+/// Regenerates keys and configs for `peers`. Existing leases are kept where
+/// still valid, so peers hold their addresses across regenerations.
+pub(super) fn generate_all(
+    cfg: &ConfigFile,
+    peers: &[Peer],
+    paths: &Paths,
+    leases: &mut Vec<Lease>,
+    files: &mut Files,
+) -> Result<()> {
+    let server_keys = keys::ensure_server_keys(paths, files)?;
 
     let v4_net: Ipv4Net = cfg.network.subnet_v4.parse().context("parsing subnet_v4")?;
     let v6_net: Option<Ipv6Net> = match cfg.network.subnet_v6.as_deref() {
@@ -17,8 +30,40 @@ pub(super) fn generate_all(cfg: &ConfigFile, peers: &[Peer], paths: &Paths) -> R
         None => None,
     };
 
-    let mut assigned_v4 = ip_alloc::gather_assigned_ips(&paths.peers)?;
-    let mut assigned_v6 = ip_alloc::gather_assigned_ips_v6(&paths.peers)?;
+    let mut public_keys = Vec::new();
+    for peer in peers {
+        let peer_dir = paths.peers.join(&peer.id);
+        files.create_dir(&peer_dir)?;
+        public_keys.push(keys::ensure_peer_keys(&peer_dir, files)?.public);
+    }
+
+    // Leases of peers that are no longer configured stay reserved for as
+    // long as their directory exists.
+    leases.retain(|lease| {
+        peers.iter().any(|peer| peer.id == lease.peer_id)
+            || paths.peers.join(&lease.peer_id).exists()
+    });
+
+    let mut assigned_v4 = HashSet::new();
+    let mut assigned_v6 = HashSet::new();
+    for lease in leases
+        .iter()
+        .filter(|lease| !peers.iter().any(|peer| peer.id == lease.peer_id))
+    {
+        assigned_v4.insert(lease.v4.to_string());
+        assigned_v6.extend(lease.v6.map(|v6| v6.to_string()));
+    }
+    let quarantine = cfg
+        .peers
+        .revoke_quarantine_hours
+        .unwrap_or(super::revoke::DEFAULT_QUARANTINE_HOURS);
+    for addr in super::revoke::quarantined_addresses(paths, quarantine)? {
+        if addr.contains(':') {
+            assigned_v6.insert(addr);
+        } else {
+            assigned_v4.insert(addr);
+        }
+    }
 
     let mut v4_hosts = v4_net.hosts();
     let server_v4 = v4_hosts
@@ -37,39 +82,104 @@ pub(super) fn generate_all(cfg: &ConfigFile, peers: &[Peer], paths: &Paths) -> R
         None
     };
 
+    let statics = ip_alloc::static_addresses(peers, &v4_net, v6_net.as_ref())?;
+    for (peer, (ip, ip6)) in peers.iter().zip(&statics) {
+        if *ip == Some(server_v4) || (ip6.is_some() && *ip6 == server_v6) {
+            anyhow::bail!("static peer address conflicts with the server address");
+        }
+        // Leases of peers that are no longer configured and quarantined
+        // addresses are reserved already; a static address may not take them.
+        if let Some(ip) = ip
+            && !assigned_v4.insert(ip.to_string())
+        {
+            anyhow::bail!(
+                "address_v4 {ip} for {} is leased to an unconfigured peer or quarantined",
+                peer.id
+            );
+        }
+        if let Some(ip6) = ip6
+            && !assigned_v6.insert(ip6.to_string())
+        {
+            anyhow::bail!(
+                "address_v6 {ip6} for {} is leased to an unconfigured peer or quarantined",
+                peer.id
+            );
+        }
+    }
+
+    // Keep each peer's leased address unless a static address replaces it or
+    // it is no longer usable; only then allocate a new one.
+    let mut kept = Vec::new();
+    for (peer, (static_v4, static_v6)) in peers.iter().zip(&statics) {
+        let lease = leases.iter().find(|lease| lease.peer_id == peer.id);
+        let v4 = static_v4.or_else(|| {
+            lease
+                .map(|lease| lease.v4)
+                .filter(|v4| v4_net.contains(v4) && assigned_v4.insert(v4.to_string()))
+        });
+        let v6 = static_v6.or_else(|| {
+            let net = v6_net.as_ref()?;
+            lease
+                .and_then(|lease| lease.v6)
+                .filter(|v6| net.contains(v6) && assigned_v6.insert(v6.to_string()))
+        });
+        kept.push((v4, v6));
+    }
+
     let mut peer_ips = Vec::new();
-    for _ in peers {
-        let ip = ip_alloc::next_available_v4(&mut v4_hosts, &assigned_v4)?;
-        assigned_v4.insert(ip.to_string());
-        let ip6 = if let Some(net) = v6_net.as_ref() {
-            let mut hosts = net.hosts();
-            let ip6 = ip_alloc::next_available_v6(&mut hosts, &assigned_v6)?;
-            assigned_v6.insert(ip6.to_string());
-            Some(ip6)
-        } else {
-            None
+    for (kept_v4, kept_v6) in kept {
+        let ip = match kept_v4 {
+            Some(ip) => ip,
+            None => {
+                let ip = ip_alloc::next_available_v4(&mut v4_hosts, &assigned_v4)?;
+                assigned_v4.insert(ip.to_string());
+                ip
+            }
+        };
+        let ip6 = match (kept_v6, v6_net.as_ref()) {
+            (Some(ip6), _) => Some(ip6),
+            (None, Some(net)) => {
+                let mut hosts = net.hosts();
+                let ip6 = ip_alloc::next_available_v6(&mut hosts, &assigned_v6)?;
+                assigned_v6.insert(ip6.to_string());
+                Some(ip6)
+            }
+            (None, None) => None,
         };
         peer_ips.push((ip, ip6));
     }
 
-    for peer in peers {
-        let peer_dir = paths.peers.join(&peer.id);
-        fs::create_dir_all(&peer_dir).context("creating peer dir")?;
-        let _ = keys::ensure_peer_keys(&peer_dir)?;
+    let now = now_secs();
+    for ((peer, (ip, ip6)), public_key) in peers.iter().zip(&peer_ips).zip(public_keys) {
+        let lease = Lease {
+            peer_id: peer.id.clone(),
+            public_key: public_key.trim().to_string(),
+            v4: *ip,
+            v6: *ip6,
+            allocated_at: now,
+        };
+        match leases.iter_mut().find(|l| l.peer_id == peer.id) {
+            Some(existing) if existing.v4 == lease.v4 && existing.v6 == lease.v6 => {
+                existing.public_key = lease.public_key;
+            }
+            Some(existing) => *existing = lease,
+            None => leases.push(lease),
+        }
     }
+    leases::save(paths, leases, files)?;
 
     server_conf::write_server_conf(
         cfg,
         paths,
         &server_keys.private,
-        server_v4,
-        server_v6,
+        (server_v4, server_v6),
         peers,
         &peer_ips,
+        files,
     )?;
 
-    for (peer, (ip, ip6)) in peers.iter().zip(peer_ips.into_iter()) {
-        peer_conf::generate_peer(cfg, paths, peer, ip, ip6, &server_keys.public)?;
+    for (peer, (ip, ip6)) in peers.iter().zip(peer_ips) {
+        peer_conf::generate_peer(cfg, paths, peer, ip, ip6, &server_keys.public, files)?;
     }
 
     Ok(())
@@ -1,9 +1,7 @@
-use crate::config::io::{read_to_string, write_atomic};
-use crate::config::qr::{print_qr, write_qr_png};
+use crate::config::files::Files;
 use crate::config::types::{ConfigFile, Paths, Peer};
 use anyhow::{Context, Result};
 use std::fmt::Write;
-use std::fs;
 
 pub(super) fn generate_peer(
     cfg: &ConfigFile,
@@ -12,11 +10,12 @@ pub(super) fn generate_peer(
     ip: std::net::Ipv4Addr,
     ip6: Option<std::net::Ipv6Addr>,
     server_public: &str,
+    files: &mut Files,
 ) -> Result<()> {
     let peer_dir = paths.peers.join(&peer.id);
-    fs::create_dir_all(&peer_dir).context("creating peer dir")?;
+    files.create_dir(&peer_dir)?;
 
-    let keys = super::keys::ensure_peer_keys(&peer_dir)?;
+    let keys = super::keys::ensure_peer_keys(&peer_dir, files)?;
 
     let mut text = String::new();
     text.push_str("[Interface]\n");
@@ -26,8 +25,12 @@ pub(super) fn generate_peer(
     }
     writeln!(text, "Address = {}", addresses.join(", "))?;
     writeln!(text, "PrivateKey = {}", keys.private.trim())?;
-    if !cfg.network.peer_dns.is_empty() {
-        writeln!(text, "DNS = {}", cfg.network.peer_dns.join(", "))?;
+    let dns = peer.dns(&cfg.network);
+    if !dns.is_empty() {
+        writeln!(text, "DNS = {}", dns.join(", "))?;
+    }
+    if let Some(mtu) = peer.mtu(&cfg.network) {
+        writeln!(text, "MTU = {mtu}")?;
     }
     text.push('\n');
 
@@ -36,19 +39,26 @@ pub(super) fn generate_peer(
         .external_address
         .as_ref()
         .context("external_address must be set to generate peer configs")?;
-    let psk = read_to_string(peer_dir.join("preshared.key"))
+    let psk = files
+        .read_to_string(&peer_dir.join("preshared.key"))
         .context("reading preshared.key for peer")?;
     text.push_str("[Peer]\n");
     writeln!(text, "PublicKey = {}", server_public.trim())?;
     writeln!(text, "PresharedKey = {}", psk.trim())?;
     writeln!(text, "Endpoint = {}:{}", external, cfg.server.listen_port)?;
-    writeln!(text, "AllowedIPs = {}", cfg.network.allowed_ips.join(", "))?;
+    writeln!(
+        text,
+        "AllowedIPs = {}",
+        peer.allowed_ips(&cfg.network).join(", ")
+    )?;
+    if let Some(keepalive) = peer.settings.persistent_keepalive {
+        writeln!(text, "PersistentKeepalive = {keepalive}")?;
+    }
 
-    write_atomic(&peer_dir.join("client.conf"), text.as_bytes())?;
+    files.write(&peer_dir.join("client.conf"), &text)?;
 
     if cfg.runtime.emit_qr {
-        print_qr(&peer_dir.join("client.conf"))?;
-        write_qr_png(&peer_dir.join("client.conf"), peer_dir.join("client.png"))?;
+        files.emit_qr(&peer_dir.join("client.conf"), peer_dir.join("client.png"))?;
     }
 
     Ok(())
@@ -1,31 +1,52 @@
-use crate::config::io::{read_to_string, write_atomic};
+use crate::config::files::Files;
 use crate::config::types::{ConfigFile, Paths, Peer};
-use anyhow::Result;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
 use std::fmt::Write;
 
 pub(super) fn write_server_conf(
     cfg: &ConfigFile,
     paths: &Paths,
     private_key: &str,
-    server_v4: std::net::Ipv4Addr,
-    server_v6: Option<std::net::Ipv6Addr>,
+    (server_v4, server_v6): (std::net::Ipv4Addr, Option<std::net::Ipv6Addr>),
     peers: &[Peer],
     peer_ips: &[(std::net::Ipv4Addr, Option<std::net::Ipv6Addr>)],
+    files: &mut Files,
 ) -> Result<()> {
     let mut text = String::new();
     text.push_str("[Interface]\n");
-    let mut addresses = vec![format!("{server_v4}/32")];
+    let (prefix_v4, prefix_v6) = if cfg.network.assign_subnet_prefix {
+        (
+            subnet_prefix(&cfg.network.subnet_v4)?,
+            match cfg.network.subnet_v6.as_deref() {
+                Some(subnet) => subnet_prefix(subnet)?,
+                None => 128,
+            },
+        )
+    } else {
+        (32, 128)
+    };
+    let mut addresses = vec![format!("{server_v4}/{prefix_v4}")];
     if let Some(v6) = server_v6 {
-        addresses.push(format!("{v6}/128"));
+        addresses.push(format!("{v6}/{prefix_v6}"));
     }
     writeln!(text, "Address = {}", addresses.join(", "))?;
     writeln!(text, "ListenPort = {}", cfg.server.listen_port)?;
+    if let Some(mtu) = cfg.network.mtu {
+        writeln!(text, "MTU = {mtu}")?;
+    }
     writeln!(text, "PrivateKey = {}\n", private_key.trim())?;
 
     for (peer, (ip, ip6)) in peers.iter().zip(peer_ips.iter()) {
-        let public_key = read_to_string(paths.peers.join(&peer.id).join("public.key"))?;
-        let psk = read_to_string(paths.peers.join(&peer.id).join("preshared.key"))?;
+        let peer_dir = paths.peers.join(&peer.id);
+        let public_key = files.read_to_string(&peer_dir.join("public.key"))?;
+        let psk = files.read_to_string(&peer_dir.join("preshared.key"))?;
 
+        if let Some(description) = peer.settings.description.as_deref() {
+            for line in description.lines() {
+                writeln!(text, "# {line}")?;
+            }
+        }
         text.push_str("[Peer]\n");
         writeln!(text, "PublicKey = {}", public_key.trim())?;
         writeln!(text, "PresharedKey = {}", psk.trim())?;
@@ -33,9 +54,17 @@ pub(super) fn write_server_conf(
         if let Some(v6) = ip6 {
             allowed.push(format!("{v6}/128"));
         }
+        allowed.extend(peer.routed_prefixes()?.iter().map(ToString::to_string));
         writeln!(text, "AllowedIPs = {}\n", allowed.join(", "))?;
     }
 
-    write_atomic(&paths.server.join("server.conf"), text.as_bytes())?;
+    files.write(&paths.server.join("server.conf"), &text)?;
     Ok(())
 }
+
+fn subnet_prefix(subnet: &str) -> Result<u8> {
+    let net: IpNet = subnet
+        .parse()
+        .with_context(|| format!("parsing subnet {subnet}"))?;
+    Ok(net.prefix_len())
+}
@@ -1,5 +1,6 @@
 use crate::config::Paths;
-use crate::config::io::{read_to_string, write_atomic};
+use crate::config::files::Files;
+use crate::config::io::read_to_string;
 use crate::config::types::ConfigFile;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
@@ -38,7 +39,7 @@ pub(super) fn inputs_changed(cfg: &ConfigFile, paths: &Paths) -> Result<bool> {
     Ok(state.digest != digest)
 }
 
-pub(super) fn write_inputs_state(cfg: &ConfigFile, paths: &Paths) -> Result<()> {
+pub(super) fn write_inputs_state(cfg: &ConfigFile, paths: &Paths, files: &mut Files) -> Result<()> {
     let snapshot = InputsSnapshot {
         server: &cfg.server,
         network: &cfg.network,
@@ -52,7 +53,7 @@ pub(super) fn write_inputs_state(cfg: &ConfigFile, paths: &Paths) -> Result<()>
         inputs: json,
     };
     let text = serde_json::to_string_pretty(&state).context("serializing inputs.json")?;
-    write_atomic(&paths.state.join("inputs.json"), text.as_bytes())?;
+    files.write(&paths.state.join("inputs.json"), &text)?;
     Ok(())
 }
 
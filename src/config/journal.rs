@@ -0,0 +1,86 @@
+use crate::config::io::{read_to_string, write_atomic};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, TryLockError};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "applied.json";
+const LOCK_FILE: &str = "run.lock";
+
+/// What `rust-wg run` has put on the system for one interface. It is written
+/// to `state/applied.json` before anything is created and removed after a
+/// clean teardown, so a file left behind means a run was killed.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Journal {
+    pub interface: String,
+    /// Peer routes on the interface, as CIDRs.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(default)]
+    pub nft_tables: Vec<NftTable>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NftTable {
+    /// `ip` or `ip6`.
+    pub family: String,
+    pub name: String,
+}
+
+pub fn write_journal(state_dir: &Path, journal: &Journal) -> Result<()> {
+    let text = serde_json::to_string_pretty(journal).context("serializing applied.json")?;
+    write_atomic(&state_dir.join(JOURNAL_FILE), text.as_bytes())
+}
+
+pub fn remove_journal(state_dir: &Path) -> Result<()> {
+    let path = state_dir.join(JOURNAL_FILE);
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("removing {}", path.display())),
+    }
+}
+
+/// An exclusive lock on `state/run.lock`, held by `rust-wg run` for as long
+/// as it runs so that nothing else acts on its journals meanwhile. The kernel
+/// releases it when the process exits, however it ends.
+pub struct RunLock {
+    _file: File,
+}
+
+/// Takes the lock in `state_dir`, failing at once if another process holds it.
+pub(super) fn lock(state_dir: &Path) -> Result<RunLock> {
+    fs::create_dir_all(state_dir).with_context(|| format!("creating {}", state_dir.display()))?;
+    let path = state_dir.join(LOCK_FILE);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(RunLock { _file: file }),
+        Err(TryLockError::WouldBlock) => anyhow::bail!(
+            "rust-wg run is running ({} is locked); stop it first",
+            path.display()
+        ),
+        Err(TryLockError::Error(err)) => {
+            Err(err).with_context(|| format!("locking {}", path.display()))
+        }
+    }
+}
+
+/// The journals in `state_dirs` that exist, with the directory each is in.
+pub(super) fn read_journals(state_dirs: &[PathBuf]) -> Result<Vec<(PathBuf, Journal)>> {
+    let mut out = Vec::new();
+    for dir in state_dirs {
+        let path = dir.join(JOURNAL_FILE);
+        if !path.exists() {
+            continue;
+        }
+        let journal = serde_json::from_str(&read_to_string(&path)?)
+            .with_context(|| format!("parsing {}", path.display()))?;
+        out.push((dir.clone(), journal));
+    }
+    Ok(out)
+}
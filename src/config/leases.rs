@@ -0,0 +1,196 @@
+use crate::config::files::Files;
+use crate::config::io::{now_secs, read_to_string};
+use crate::config::types::Paths;
+use anyhow::{Context, Result};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+/// Addresses allocated to one peer, as recorded in `state/leases.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Lease {
+    pub peer_id: String,
+    pub public_key: String,
+    pub v4: Ipv4Addr,
+    pub v6: Option<Ipv6Addr>,
+    pub allocated_at: u64,
+}
+
+impl Lease {
+    /// The peer's host routes, also used as its WireGuard allowed IPs.
+    pub fn addresses(&self) -> Vec<IpNet> {
+        let mut out = vec![IpNet::V4(Ipv4Net::from(self.v4))];
+        out.extend(self.v6.map(|v6| IpNet::V6(Ipv6Net::from(v6))));
+        out
+    }
+}
+
+/// A lease a dry run would allocate, move or release.
+#[derive(Debug, Serialize)]
+pub struct LeaseChange {
+    pub peer_id: String,
+    /// Empty when the lease is released.
+    pub addresses: Vec<String>,
+    /// Empty when the lease is new.
+    pub previous: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct LeaseFile {
+    leases: Vec<Lease>,
+}
+
+/// Loads the lease database, building it from existing `client.conf` files
+/// the first time it is needed.
+pub(super) fn load(paths: &Paths, files: &mut Files) -> Result<Vec<Lease>> {
+    let path = leases_path(paths);
+    if !path.exists() {
+        let leases = migrate(paths)?;
+        if !leases.is_empty() {
+            eprintln!(
+                "config: migrated {} peer addresses into {}",
+                leases.len(),
+                path.display()
+            );
+        }
+        save(paths, &leases, files)?;
+        return Ok(leases);
+    }
+    let file: LeaseFile =
+        serde_json::from_str(&read_to_string(&path)?).context("parsing leases.json")?;
+    Ok(file.leases)
+}
+
+pub(super) fn save(paths: &Paths, leases: &[Lease], files: &mut Files) -> Result<()> {
+    let file = LeaseFile {
+        leases: leases.to_vec(),
+    };
+    let text = serde_json::to_string_pretty(&file).context("serializing leases.json")?;
+    files.write(&leases_path(paths), &text)
+}
+
+/// The leases whose addresses differ between `before` and `after`.
+pub(super) fn changes(before: &[Lease], after: &[Lease]) -> Vec<LeaseChange> {
+    let addresses = |leases: &[Lease], peer_id: &str| -> Vec<String> {
+        leases
+            .iter()
+            .filter(|lease| lease.peer_id == peer_id)
+            .flat_map(|lease| lease.addresses())
+            .map(|addr| addr.addr().to_string())
+            .collect()
+    };
+    let mut out = Vec::new();
+    for lease in after.iter().chain(before) {
+        if out
+            .iter()
+            .any(|change: &LeaseChange| change.peer_id == lease.peer_id)
+        {
+            continue;
+        }
+        let change = LeaseChange {
+            peer_id: lease.peer_id.clone(),
+            addresses: addresses(after, &lease.peer_id),
+            previous: addresses(before, &lease.peer_id),
+        };
+        if change.addresses != change.previous {
+            out.push(change);
+        }
+    }
+    out
+}
+
+fn leases_path(paths: &Paths) -> PathBuf {
+    paths.state.join("leases.json")
+}
+
+fn migrate(paths: &Paths) -> Result<Vec<Lease>> {
+    let mut leases = Vec::new();
+    if !paths.peers.exists() {
+        return Ok(leases);
+    }
+    let mut entries = fs::read_dir(&paths.peers)
+        .context("reading peers dir")?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let peer_dir = entry.path();
+        let (conf, public) = (peer_dir.join("client.conf"), peer_dir.join("public.key"));
+        if !conf.exists() || !public.exists() {
+            continue;
+        }
+        let mut v4 = None;
+        let mut v6 = None;
+        for addr in extract_addresses(&read_to_string(&conf)?) {
+            match addr.parse::<IpNet>() {
+                Ok(IpNet::V4(net)) => v4 = v4.or(Some(net.addr())),
+                Ok(IpNet::V6(net)) => v6 = v6.or(Some(net.addr())),
+                Err(_) => {}
+            }
+        }
+        let Some(v4) = v4 else {
+            continue;
+        };
+        leases.push(Lease {
+            peer_id: entry.file_name().to_string_lossy().into_owned(),
+            public_key: read_to_string(&public)?.trim().to_string(),
+            v4,
+            v6,
+            allocated_at: now_secs(),
+        });
+    }
+    Ok(leases)
+}
+
+fn extract_addresses(text: &str) -> Vec<String> {
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Address") {
+            let parts: Vec<&str> = rest.split('=').collect();
+            if parts.len() == 2 {
+                return parts[1]
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect();
+            }
+        }
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::scratch::ScratchDir;
+
+    #[test]
+    fn migrates_addresses_from_client_conf() {
+        let dir = ScratchDir::new("leases");
+        let paths = Paths::under(dir.path());
+        let peer_dir = paths.peers.join("peer-phone");
+        fs::create_dir_all(&peer_dir).unwrap();
+        fs::create_dir_all(&paths.state).unwrap();
+        fs::write(peer_dir.join("public.key"), "cHVibGlj\n").unwrap();
+        fs::write(
+            peer_dir.join("client.conf"),
+            "[Interface]\nAddress = 10.66.0.7/32, fd66::7/128\n",
+        )
+        .unwrap();
+        fs::create_dir_all(paths.peers.join("peer-empty")).unwrap();
+
+        let leases = load(&paths, &mut Files::disk()).unwrap();
+        let reloaded = load(&paths, &mut Files::disk()).unwrap();
+
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].peer_id, "peer-phone");
+        assert_eq!(leases[0].public_key, "cHVibGlj");
+        assert_eq!(leases[0].v4, Ipv4Addr::new(10, 66, 0, 7));
+        assert_eq!(leases[0].v6, Some("fd66::7".parse().unwrap()));
+        assert_eq!(reloaded, leases);
+    }
+}
@@ -1,48 +1,552 @@
 use anyhow::{Context, Result};
+use files::Files;
+use serde::Serialize;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 mod assets;
 mod env;
+mod files;
 mod generate;
 mod inputs;
 mod io;
+mod journal;
+mod leases;
 mod peers;
+mod provenance;
 mod qr;
+mod revoke;
+#[cfg(test)]
+mod scratch;
 mod types;
+mod validate;
 
+pub use files::PlannedFile;
+pub use generate::{decode_key, encode_key};
+pub use journal::{Journal, NftTable, RunLock, remove_journal, write_journal};
+pub use leases::LeaseChange;
+pub use provenance::{EffectiveConfig, Setting, effective};
+pub use qr::print_qr;
+pub use revoke::{PendingRevocation, RevokedPeer};
 pub use types::{
-    ConfigFile, NetworkConfig, Paths, PeersConfig, ResolvedConfig, RuntimeConfigFile, ServerConfig,
+    AclConfig, AclEntry, AclRules, ConfigFile, DEFAULT_INTERFACE, EventsConfig, NatMode,
+    NetworkConfig, Paths, PeersConfig, Policy, PortForward, Protocol, ResolvedConfig,
+    RuntimeConfigFile, ServerConfig,
 };
+pub use validate::Problem;
+// For building a `ResolvedConfig` by hand in other modules' tests.
+#[cfg(test)]
+pub use {
+    leases::Lease,
+    types::{Peer, PeerSettings},
+};
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/wg/wg.toml";
+const DEFAULT_STATE_DIR: &str = "/var/lib/wg";
+
+/// Where to read the config file and keep state, as given on the command
+/// line; these win over `WG_CONFIG`/`WG_STATE_DIR` and `[paths]`.
+#[derive(Debug, Clone, Default)]
+pub struct Locations {
+    pub config: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+}
 
-pub fn prepare() -> Result<ResolvedConfig> {
-    let mut cfg = load_config_file(&config_path())?;
-    env::apply_env_overrides(&mut cfg);
+/// What [`plan`] found `prepare` would write and allocate for one interface.
+#[derive(Debug, Serialize)]
+pub struct GeneratePlan {
+    pub files: Vec<PlannedFile>,
+    pub leases: Vec<LeaseChange>,
+    /// IDs of new `count` peers, which are random: a real run picks others.
+    pub random_ids: Vec<String>,
+}
 
-    let paths = Paths {
-        root: PathBuf::from("/var/lib/wg"),
-        keys: PathBuf::from("/var/lib/wg/keys"),
-        peers: PathBuf::from("/var/lib/wg/peers"),
-        server: PathBuf::from("/var/lib/wg/server"),
-        state: PathBuf::from("/var/lib/wg/state"),
+/// Every problem with the config file and the environment overrides or,
+/// when there are none, whatever else stops generation; empty when `run`
+/// would accept the configuration. Writes nothing.
+pub fn check(locations: &Locations) -> Result<(PathBuf, Vec<Problem>)> {
+    let (path, text) = read_config_text(locations)?;
+    if let Err(problems) = validate::parse(&path, &text) {
+        return Ok((path, problems));
+    }
+    let problems = match plan(locations) {
+        Ok(_) => Vec::new(),
+        Err(err) => vec![Problem {
+            location: path.display().to_string(),
+            message: format!("{err:#}"),
+        }],
     };
+    Ok((path, problems))
+}
 
-    assets::ensure_dirs(&paths)?;
+/// Loads and, where inputs changed, regenerates every configured interface.
+pub fn prepare(locations: &Locations) -> Result<Vec<ResolvedConfig>> {
+    let resolved = load(locations)?
+        .into_iter()
+        .map(|iface| Ok(prepare_interface(iface, &mut Files::disk())?.0))
+        .collect::<Result<Vec<_>>>()?;
+    ensure_unique_forwards(&resolved)?;
+    Ok(resolved)
+}
+
+/// Like [`prepare`], but keeps every write in memory; returns each resolved
+/// interface with what would have been written and allocated for it.
+pub fn plan(locations: &Locations) -> Result<Vec<(ResolvedConfig, GeneratePlan)>> {
+    let mut out = Vec::new();
+    for iface in load(locations)? {
+        let mut files = Files::dry_run();
+        let (config, leases) = prepare_interface(iface, &mut files)?;
+        let files = files.into_plan();
+        let random_ids = config
+            .peers
+            .iter()
+            .filter(|peer| peer.name.is_none() && !config.paths.peers.join(&peer.id).exists())
+            .map(|peer| peer.id.clone())
+            .collect();
+        out.push((
+            config,
+            GeneratePlan {
+                files,
+                leases,
+                random_ids,
+            },
+        ));
+    }
+    let resolved: Vec<_> = out.iter().map(|(config, _)| config.clone()).collect();
+    ensure_unique_forwards(&resolved)?;
+    Ok(out)
+}
+
+fn prepare_interface(
+    iface: Interface,
+    files: &mut Files,
+) -> Result<(ResolvedConfig, Vec<LeaseChange>)> {
+    let Interface { name, cfg, paths } = iface;
+    assets::ensure_dirs(&paths, files)?;
 
     let peers = peers::resolve_peers(&cfg.peers, &paths)?;
-    let regen_needed =
-        inputs::inputs_changed(&cfg, &paths)? || assets::assets_missing(&paths, &peers);
+    revoke::ensure_not_revoked(&paths, &peers)?;
+    let mut leases = leases::load(&paths, files)?;
+    let previous = leases.clone();
+    let regen_needed = inputs::inputs_changed(&cfg, &paths)?
+        || assets::assets_missing(&paths, &peers)
+        || peers
+            .iter()
+            .any(|peer| !leases.iter().any(|lease| lease.peer_id == peer.id));
     if regen_needed {
-        generate::generate_all(&cfg, &peers, &paths)?;
-        inputs::write_inputs_state(&cfg, &paths)?;
+        generate::generate_all(&cfg, &peers, &paths, &mut leases, files)
+            .with_context(|| format!("generating configs for {name}"))?;
+        inputs::write_inputs_state(&cfg, &paths, files)?;
     }
+    let changes = leases::changes(&previous, &leases);
+    leases.retain(|lease| peers.iter().any(|peer| peer.id == lease.peer_id));
+    let forwards = resolve_forwards(&cfg, &paths)?;
 
-    Ok(ResolvedConfig {
+    let config = ResolvedConfig {
+        interface: name,
         server: cfg.server,
         network: cfg.network,
         peers,
+        acl: cfg.acl,
+        forwards,
+        leases,
         paths,
-    })
+    };
+    Ok((config, changes))
+}
+
+/// Resolves a peer ID or configured name to the peer's `client.conf`.
+pub fn peer_conf_path(
+    locations: &Locations,
+    query: &str,
+    interface: Option<&str>,
+) -> Result<PathBuf> {
+    let (iface, id) = locate_peer(locations, query, interface)?;
+    let conf = iface.paths.peers.join(&id).join("client.conf");
+    if !conf.exists() {
+        anyhow::bail!("peer {id} has no client.conf; run `rust-wg generate` first");
+    }
+    Ok(conf)
+}
+
+/// Finds a peer to revoke by ID or configured name. Peers the configuration
+/// still lists are refused, since the next generate would issue them new keys.
+pub fn pending_revocation(
+    locations: &Locations,
+    query: &str,
+    interface: Option<&str>,
+) -> Result<PendingRevocation> {
+    let (iface, id) = locate_peer(locations, query, interface)?;
+    let configured = peers::resolve_peers(&iface.cfg.peers, &iface.paths)?;
+    if let Some(peer) = configured.iter().find(|peer| peer.id == id) {
+        match &peer.name {
+            Some(name) => anyhow::bail!(
+                "peer {name} is still configured on {}; remove it from [peers] first",
+                iface.name
+            ),
+            None => anyhow::bail!(
+                "{id} is one of the `count` peers of {}; lower [peers] count until it \
+                 is left out (the first peers by ID are kept) first",
+                iface.name
+            ),
+        }
+    }
+    assets::ensure_dirs(&iface.paths, &mut Files::disk())?;
+    revoke::pending(iface.name, iface.paths, id)
+}
+
+/// Records the revocation of a peer from [`pending_revocation`] and moves its
+/// keys to `revoked/`, or deletes them.
+pub fn revoke_peer(pending: &PendingRevocation, delete_keys: bool) -> Result<RevokedPeer> {
+    revoke::revoke(pending, delete_keys)
+}
+
+/// Where `rust-wg run` should serve metrics, if anywhere.
+pub fn metrics_listen(locations: &Locations) -> Result<Option<SocketAddr>> {
+    read_config(locations)?
+        .metrics
+        .listen
+        .map(|addr| {
+            addr.parse()
+                .with_context(|| format!("parsing [metrics] listen address {addr}"))
+        })
+        .transpose()
+}
+
+/// Hooks and webhook for peer events of `rust-wg run`.
+pub fn events_config(locations: &Locations) -> Result<EventsConfig> {
+    Ok(read_config(locations)?.events)
+}
+
+/// Journals left under the state root, by `wg0` in the single-interface
+/// layout or by any interface directory, configured or not.
+pub fn recorded_journals(locations: &Locations) -> Result<Vec<(PathBuf, Journal)>> {
+    let root = state_root(&read_config(locations)?);
+    let mut dirs = vec![root.join("state")];
+    if let Ok(entries) = fs::read_dir(&root) {
+        let mut subdirs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().join("state"))
+            .filter(|dir| dir.is_dir())
+            .collect();
+        subdirs.sort();
+        dirs.extend(subdirs);
+    }
+    journal::read_journals(&dirs)
+}
+
+/// Takes the lock `rust-wg run` holds while it runs, in the `state/` dir of
+/// the state root; fails if a run holds it already.
+pub fn lock_run(locations: &Locations) -> Result<RunLock> {
+    journal::lock(&state_root(&read_config(locations)?).join("state"))
+}
+
+/// Names of the configured interfaces.
+pub fn interface_names(locations: &Locations) -> Result<Vec<String>> {
+    Ok(load(locations)?
+        .into_iter()
+        .map(|iface| iface.name)
+        .collect())
+}
+
+/// A peer generated under `Paths::peers`.
+#[derive(Clone)]
+pub struct PeerRecord {
+    pub id: String,
+    /// The configured name the peer was generated for, if still configured.
+    pub name: Option<String>,
+    /// Base64 public key; `None` if `public.key` is missing.
+    pub public_key: Option<String>,
+}
+
+/// Every configured interface with the peers generated for it, without
+/// regenerating anything.
+pub fn generated_peers(locations: &Locations) -> Result<Vec<(String, Vec<PeerRecord>)>> {
+    let mut out = Vec::new();
+    for iface in load(locations)? {
+        let mut records = Vec::new();
+        for (id, name) in peers::generated_peers(&iface.cfg.peers, &iface.paths)? {
+            let public_key = fs::read_to_string(iface.paths.peers.join(&id).join("public.key"))
+                .ok()
+                .map(|key| key.trim().to_string());
+            records.push(PeerRecord {
+                id,
+                name,
+                public_key,
+            });
+        }
+        out.push((iface.name, records));
+    }
+    Ok(out)
+}
+
+/// The peers of an applied configuration, with the keys they were leased to.
+pub fn peer_records(config: &ResolvedConfig) -> Vec<PeerRecord> {
+    config
+        .peers
+        .iter()
+        .map(|peer| PeerRecord {
+            id: peer.id.clone(),
+            name: peer.name.clone(),
+            public_key: config.lease(&peer.id).map(|lease| lease.public_key.clone()),
+        })
+        .collect()
+}
+
+/// The record of the peer a device reports by `public_key`, if any.
+pub fn find_record<'a>(records: &'a [PeerRecord], public_key: &str) -> Option<&'a PeerRecord> {
+    records
+        .iter()
+        .find(|record| record.public_key.as_deref() == Some(public_key))
+}
+
+/// Finds the interface whose peers `query` names: `interface` if given,
+/// otherwise the only one that knows the peer.
+fn locate_peer(
+    locations: &Locations,
+    query: &str,
+    interface: Option<&str>,
+) -> Result<(Interface, String)> {
+    let mut interfaces = load(locations)?;
+    if let Some(name) = interface {
+        interfaces.retain(|iface| iface.name == name);
+        if interfaces.is_empty() {
+            anyhow::bail!("no interface named {name} is configured");
+        }
+    }
+    if interfaces.len() == 1 {
+        let iface = interfaces.remove(0);
+        let id = peers::find_peer(query, &iface.cfg.peers, &iface.paths)?;
+        return Ok((iface, id));
+    }
+
+    let mut found = Vec::new();
+    for iface in interfaces {
+        if let Ok(id) = peers::find_peer(query, &iface.cfg.peers, &iface.paths) {
+            found.push((iface, id));
+        }
+    }
+    match found.len() {
+        0 => anyhow::bail!("unknown peer {query} on any interface"),
+        1 => Ok(found.remove(0)),
+        _ => {
+            let names: Vec<_> = found.iter().map(|(iface, _)| iface.name.as_str()).collect();
+            anyhow::bail!(
+                "peer {query} exists on {}; pick one with --interface",
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// Points each `[[forward]]` at a peer ID and rejects duplicate external ports.
+fn resolve_forwards(cfg: &ConfigFile, paths: &Paths) -> Result<Vec<PortForward>> {
+    let mut forwards = Vec::new();
+    for forward in &cfg.forwards {
+        let peer = peers::find_peer(&forward.peer, &cfg.peers, paths)
+            .with_context(|| format!("resolving forward of port {}", forward.external_port))?;
+        forwards.push(PortForward {
+            peer,
+            ..forward.clone()
+        });
+    }
+    Ok(forwards)
+}
+
+/// External ports are shared by all interfaces, so each may be forwarded once.
+fn ensure_unique_forwards(resolved: &[ResolvedConfig]) -> Result<()> {
+    let mut seen = Vec::new();
+    for forward in resolved.iter().flat_map(|config| &config.forwards) {
+        let key = (forward.proto, forward.external_port);
+        if seen.contains(&key) {
+            anyhow::bail!(
+                "external port {}/{} is forwarded more than once",
+                forward.proto.name(),
+                forward.external_port
+            );
+        }
+        seen.push(key);
+    }
+    Ok(())
+}
+
+/// One interface's settings and its state directory.
+struct Interface {
+    name: String,
+    cfg: ConfigFile,
+    paths: Paths,
+}
+
+/// Splits the config into interfaces. Without `[[interface]]`, the top-level
+/// sections describe `wg0`, whose state lives directly under the state root;
+/// otherwise each interface gets a subdirectory named after it.
+fn load(locations: &Locations) -> Result<Vec<Interface>> {
+    let cfg = read_config(locations)?;
+    let root = state_root(&cfg);
+
+    if cfg.interfaces.is_empty() {
+        return Ok(vec![Interface {
+            name: DEFAULT_INTERFACE.to_string(),
+            paths: Paths::under(&root),
+            cfg,
+        }]);
+    }
+
+    let mut interfaces: Vec<Interface> = Vec::new();
+    for entry in &cfg.interfaces {
+        validate_interface_name(&entry.name)?;
+        if interfaces.iter().any(|iface| iface.name == entry.name) {
+            anyhow::bail!("interface {} is configured more than once", entry.name);
+        }
+        let iface_cfg = ConfigFile {
+            server: entry.server.clone().unwrap_or_else(|| cfg.server.clone()),
+            network: entry.network.clone().unwrap_or_else(|| cfg.network.clone()),
+            peers: entry.peers.clone().unwrap_or_else(|| cfg.peers.clone()),
+            runtime: cfg.runtime.clone(),
+            acl: entry.acl.clone().unwrap_or_else(|| cfg.acl.clone()),
+            forwards: entry
+                .forwards
+                .clone()
+                .unwrap_or_else(|| cfg.forwards.clone()),
+            interfaces: Vec::new(),
+            paths: cfg.paths.clone(),
+            metrics: cfg.metrics.clone(),
+            events: cfg.events.clone(),
+        };
+        if let Some(other) = interfaces
+            .iter()
+            .find(|iface| iface.cfg.server.listen_port == iface_cfg.server.listen_port)
+        {
+            anyhow::bail!(
+                "interfaces {} and {} both listen on port {}",
+                other.name,
+                entry.name,
+                iface_cfg.server.listen_port
+            );
+        }
+        for other in &interfaces {
+            for (key, subnet) in claimed_subnets(&iface_cfg.network) {
+                for (other_key, other_subnet) in claimed_subnets(&other.cfg.network) {
+                    if subnets_overlap(subnet, other_subnet) {
+                        anyhow::bail!(
+                            "{} {other_key} {other_subnet} overlaps {} {key} {subnet}",
+                            other.name,
+                            entry.name
+                        );
+                    }
+                }
+            }
+        }
+        interfaces.push(Interface {
+            name: entry.name.clone(),
+            paths: Paths::under(&interface_root(&root, &entry.name)),
+            cfg: iface_cfg,
+        });
+    }
+    let implicit = Paths::under(&root);
+    if !interfaces
+        .iter()
+        .any(|iface| iface.name == DEFAULT_INTERFACE)
+        && implicit.server.exists()
+    {
+        anyhow::bail!(
+            "{} holds the state of {DEFAULT_INTERFACE}, which no [[interface]] is named; \
+             name one {DEFAULT_INTERFACE} to keep its keys and peers, or move the state \
+             into {} first",
+            root.display(),
+            root.join("<interface>").display()
+        );
+    }
+    Ok(interfaces)
+}
+
+/// `wg0` keeps the state root of the single-interface layout, so declaring
+/// it as an `[[interface]]` keeps its keys, leases and peers; every other
+/// interface gets a directory of its own under the root.
+fn interface_root(root: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT_INTERFACE {
+        root.to_path_buf()
+    } else {
+        root.join(name)
+    }
+}
+
+/// The config file with env and command-line overrides applied; fails
+/// listing every problem [`validate`] finds.
+fn read_config(locations: &Locations) -> Result<ConfigFile> {
+    Ok(read_parsed_config(locations)?.cfg)
+}
+
+fn read_parsed_config(locations: &Locations) -> Result<validate::Parsed> {
+    let (path, text) = read_config_text(locations)?;
+    let mut parsed = validate::parse(&path, &text).map_err(|problems| {
+        let lines: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        anyhow::anyhow!("invalid configuration:\n  {}", lines.join("\n  "))
+    })?;
+    if let Some(dir) = &locations.state_dir {
+        parsed.cfg.paths.state_dir = Some(dir.clone());
+    }
+    Ok(parsed)
+}
+
+/// The config file path and its content, empty if the default file is
+/// missing.
+fn read_config_text(locations: &Locations) -> Result<(PathBuf, String)> {
+    let path = match &locations.config {
+        Some(path) if !path.exists() => anyhow::bail!("config file {} not found", path.display()),
+        Some(path) => path.clone(),
+        None => config_path(),
+    };
+    if !path.exists() {
+        return Ok((path, String::new()));
+    }
+    let text = fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    Ok((path, text))
+}
+
+fn state_root(cfg: &ConfigFile) -> PathBuf {
+    cfg.paths
+        .state_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_DIR))
+}
+
+/// Interface names double as state directory names, so besides the kernel's
+/// limits they may not shadow the single-interface layout.
+fn validate_interface_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if name.is_empty() || name.len() > 15 || !valid_chars {
+        anyhow::bail!("invalid interface name {name:?}: use 1-15 letters, digits, '-' or '_'");
+    }
+    if ["keys", "peers", "server", "state", "revoked"].contains(&name) {
+        anyhow::bail!("interface name {name} is reserved for the state directory layout");
+    }
+    Ok(())
+}
+
+/// The prefixes an interface's traffic uses, which no other interface may
+/// share: its subnets and the prefix NPTv6 maps `subnet_v6` onto.
+fn claimed_subnets(network: &NetworkConfig) -> Vec<(&'static str, &str)> {
+    let mut out = vec![("subnet_v4", network.subnet_v4.as_str())];
+    out.extend(network.subnet_v6.as_deref().map(|net| ("subnet_v6", net)));
+    out.extend(
+        network
+            .npt_prefix_v6
+            .as_deref()
+            .map(|net| ("npt_prefix_v6", net)),
+    );
+    out
+}
+
+/// Whether two CIDRs share addresses; different families never do.
+fn subnets_overlap(a: &str, b: &str) -> bool {
+    match (a.parse::<ipnet::IpNet>(), b.parse::<ipnet::IpNet>()) {
+        (Ok(a), Ok(b)) => a.contains(&b.network()) || b.contains(&a.network()),
+        _ => false,
+    }
 }
 
 fn config_path() -> PathBuf {
@@ -51,14 +555,236 @@ fn config_path() -> PathBuf {
     {
         return PathBuf::from(path);
     }
-    PathBuf::from("/etc/wg/wg.toml")
+    PathBuf::from(DEFAULT_CONFIG_PATH)
 }
 
-fn load_config_file(path: &Path) -> Result<ConfigFile> {
-    if !path.exists() {
-        return Ok(ConfigFile::default());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scratch::ScratchDir;
+
+    #[test]
+    fn prepares_into_configured_state_dir() {
+        let dir = ScratchDir::new("prepare");
+        let locations = dir.locations(
+            "[server]\nlisten_port = 51820\nexternal_address = \"vpn.example.com\"\n\n\
+             [peers]\nnames = [\"phone\"]\n\n[runtime]\nemit_qr = false\n",
+        );
+
+        let resolved = prepare(&locations).unwrap();
+
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].interface, DEFAULT_INTERFACE);
+        assert_eq!(resolved[0].paths.root, dir.path().join("state"));
+        assert!(
+            dir.path()
+                .join("state/peers/peer-phone/client.conf")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn plans_without_touching_the_state_dir() {
+        let dir = ScratchDir::new("plan");
+        let locations = dir.locations(
+            "[server]\nlisten_port = 51820\nexternal_address = \"vpn.example.com\"\n\n\
+             [peers]\ncount = 2\n\n[runtime]\nemit_qr = false\n",
+        );
+
+        let planned = plan(&locations).unwrap();
+
+        assert_eq!(planned.len(), 1);
+        let (config, generated) = &planned[0];
+        assert!(
+            generated
+                .files
+                .iter()
+                .any(|file| file.path.ends_with("server/server.conf"))
+        );
+        assert_eq!(generated.leases.len(), 2);
+        let ids: Vec<_> = config.peers.iter().map(|peer| peer.id.clone()).collect();
+        assert_eq!(generated.random_ids, ids);
+        let entries: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["wg.toml"]);
+    }
+
+    #[test]
+    fn finds_journals_of_unconfigured_interfaces() {
+        let dir = ScratchDir::new("journal");
+        let locations = dir.locations("");
+        let old_state = dir.path().join("state/wg-old/state");
+        fs::create_dir_all(&old_state).unwrap();
+        let journal = Journal {
+            interface: "wg-old".to_string(),
+            ..Journal::default()
+        };
+        write_journal(&old_state, &journal).unwrap();
+
+        let found = recorded_journals(&locations).unwrap();
+        remove_journal(&old_state).unwrap();
+        let after = recorded_journals(&locations).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, old_state);
+        assert_eq!(found[0].1.interface, "wg-old");
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn refuses_to_revoke_configured_peers_and_reuse_their_addresses() {
+        let dir = ScratchDir::new("revoke");
+        let config = |peers: &str| {
+            format!(
+                "[server]\nlisten_port = 51820\nexternal_address = \"vpn.example.com\"\n\n\
+                 [peers]\n{peers}\n\n[runtime]\nemit_qr = false\n"
+            )
+        };
+        let locations = dir.locations(&config("names = [\"phone\", \"laptop\"]"));
+        prepare(&locations).unwrap();
+        let err = pending_revocation(&locations, "laptop", None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "peer laptop is still configured on wg0; remove it from [peers] first"
+        );
+
+        let locations = dir.locations(&config("count = 2"));
+        let err = pending_revocation(&locations, "peer-laptop", None)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("`count` peers"), "{err}");
+
+        let locations = dir.locations(&config("names = [\"phone\"]"));
+        let pending = pending_revocation(&locations, "peer-laptop", None).unwrap();
+        let revoked = revoke_peer(&pending, false).unwrap();
+        assert_eq!(revoked.addresses, ["10.66.0.3/32"]);
+        assert!(revoked.archive.unwrap().join("public.key").exists());
+        assert!(!dir.path().join("state/peers/peer-laptop").exists());
+        prepare(&locations).unwrap();
+
+        let locations = dir.locations(&config(
+            "names = [\"phone\"]\n[[peers.peer]]\nname = \"phone\"\naddress_v4 = \"10.66.0.3\"",
+        ));
+        let err = prepare(&locations).err().unwrap();
+        assert_eq!(
+            format!("{:#}", err.root_cause()),
+            "address_v4 10.66.0.3 for peer-phone is leased to an unconfigured peer or quarantined"
+        );
+    }
+
+    #[test]
+    fn holds_the_run_lock_exclusively() {
+        let dir = ScratchDir::new("lock");
+        let locations = dir.locations("");
+        let lock = lock_run(&locations).unwrap();
+        let err = lock_run(&locations).err().unwrap();
+        assert!(err.to_string().contains("rust-wg run is running"), "{err}");
+        drop(lock);
+        assert!(lock_run(&locations).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_interface_names_and_overlapping_prefixes() {
+        assert!(validate_interface_name("wg-guest_2").is_ok());
+        for name in ["", "wg guest", "a-very-long-interface", "peers"] {
+            assert!(validate_interface_name(name).is_err(), "{name:?}");
+        }
+
+        assert!(subnets_overlap("10.66.0.0/24", "10.66.0.128/25"));
+        assert!(subnets_overlap("fd66::/48", "fd66:0:0:1::/64"));
+        assert!(!subnets_overlap("10.66.0.0/24", "10.67.0.0/24"));
+        assert!(!subnets_overlap("10.66.0.0/24", "fd66::/64"));
+
+        let dir = ScratchDir::new("overlap");
+        let locations = dir.locations(
+            "[runtime]\nemit_qr = false\n\
+             [[interface]]\nname = \"wg0\"\n\
+             [interface.network]\nsubnet_v4 = \"10.66.0.0/24\"\nsubnet_v6 = \"fd66::/64\"\n\
+             allowed_ips = []\npeer_dns = []\n\
+             [[interface]]\nname = \"wg1\"\n[interface.server]\nlisten_port = 51821\n\
+             external_address = \"vpn.example.com\"\n\
+             [interface.network]\nsubnet_v4 = \"10.67.0.0/24\"\nsubnet_v6 = \"fd67::/64\"\n\
+             nat_v6 = \"npt\"\nnpt_prefix_v6 = \"fd66::/64\"\nallowed_ips = []\npeer_dns = []\n",
+        );
+        let err = load(&locations).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "wg0 subnet_v6 fd66::/64 overlaps wg1 npt_prefix_v6 fd66::/64"
+        );
+    }
+
+    #[test]
+    fn keeps_wg0_state_when_declaring_interfaces() {
+        let dir = ScratchDir::new("layout");
+        let single = "[runtime]\nemit_qr = false\n\
+                      [server]\nlisten_port = 51820\nexternal_address = \"vpn.example.com\"\n\
+                      [peers]\nnames = [\"phone\"]\n";
+        let locations = dir.locations(single);
+        prepare(&locations).unwrap();
+        let public_key =
+            || fs::read_to_string(dir.path().join("state/peers/peer-phone/public.key")).unwrap();
+        let before = public_key();
+
+        let wg1 = "[[interface]]\nname = \"wg1\"\n[interface.server]\nlisten_port = 51821\n\
+                   external_address = \"vpn.example.com\"\n\
+                   [interface.network]\nsubnet_v4 = \"10.67.0.0/24\"\nallowed_ips = []\n\
+                   peer_dns = []\n";
+        let locations = dir.locations(&format!("{single}{wg1}"));
+        let err = load(&locations).err().unwrap();
+        assert!(
+            err.to_string().contains("no [[interface]] is named"),
+            "{err}"
+        );
+
+        let locations = dir.locations(&format!("{single}[[interface]]\nname = \"wg0\"\n{wg1}"));
+        let resolved = prepare(&locations).unwrap();
+        assert_eq!(resolved[0].paths.root, dir.path().join("state"));
+        assert_eq!(resolved[1].paths.root, dir.path().join("state/wg1"));
+        assert_eq!(public_key(), before);
+    }
+
+    #[test]
+    fn locates_peers_and_rejects_duplicate_forwards() {
+        let dir = ScratchDir::new("locate");
+        let locations = dir.locations(
+            "[runtime]\nemit_qr = false\n\
+             [server]\nlisten_port = 51820\nexternal_address = \"vpn.example.com\"\n\
+             [network]\nsubnet_v4 = \"10.66.0.0/24\"\nallowed_ips = []\npeer_dns = []\n\
+             [[forward]]\nproto = \"tcp\"\nexternal_port = 2222\npeer = \"phone\"\n\
+             [[interface]]\nname = \"wg0\"\n[interface.peers]\nnames = [\"phone\", \"laptop\"]\n\
+             [[interface]]\nname = \"wg1\"\n[interface.server]\nlisten_port = 51821\n\
+             external_address = \"vpn.example.com\"\n\
+             [interface.network]\nsubnet_v4 = \"10.67.0.0/24\"\nallowed_ips = []\npeer_dns = []\n\
+             [interface.peers]\nnames = [\"phone\"]\n",
+        );
+        let err = prepare(&locations).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "external port tcp/2222 is forwarded more than once"
+        );
+
+        let locations = dir.locations(
+            &fs::read_to_string(dir.path().join("wg.toml"))
+                .unwrap()
+                .replace(
+                    "[[forward]]\nproto = \"tcp\"\nexternal_port = 2222\npeer = \"phone\"\n",
+                    "",
+                ),
+        );
+        prepare(&locations).unwrap();
+        let (iface, id) = locate_peer(&locations, "laptop", None).unwrap();
+        assert_eq!((iface.name.as_str(), id.as_str()), ("wg0", "peer-laptop"));
+        let (iface, _) = locate_peer(&locations, "phone", Some("wg1")).unwrap();
+        assert_eq!(iface.name, "wg1");
+        let err = locate_peer(&locations, "phone", None).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "peer phone exists on wg0, wg1; pick one with --interface"
+        );
+        assert!(locate_peer(&locations, "phone", Some("wg9")).is_err());
     }
-    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
-    let cfg: ConfigFile = toml::from_str(&text).context("parsing wg.toml")?;
-    Ok(cfg)
 }
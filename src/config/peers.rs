@@ -1,24 +1,24 @@
-use crate::config::types::{Paths, Peer, PeersConfig};
+use crate::config::types::{Paths, Peer, PeerSettings, PeersConfig};
 use anyhow::{Context, Result};
 use std::collections::HashSet;
 use std::fs;
 use uuid::Uuid;
 
 pub(super) fn resolve_peers(peers: &PeersConfig, paths: &Paths) -> Result<Vec<Peer>> {
-    if let Some(names) = peers.names.as_ref().filter(|names| !names.is_empty()) {
+    let named = named_peers(peers);
+    if !named.is_empty() {
         let mut seen = HashSet::new();
         let mut peers_out = Vec::new();
-        for (idx, name) in names.iter().enumerate() {
-            let slug = slugify(name);
-            let peer_id = if slug.is_empty() {
-                format!("peer-unnamed-{}", idx + 1)
-            } else {
-                format!("peer-{slug}")
-            };
+        for (idx, (name, settings)) in named.into_iter().enumerate() {
+            let peer_id = peer_id_for_name(idx, &name);
             if !seen.insert(peer_id.clone()) {
                 anyhow::bail!("duplicate peer name after slugging: {name}");
             }
-            peers_out.push(Peer { id: peer_id });
+            peers_out.push(Peer {
+                id: peer_id,
+                name: Some(name),
+                settings,
+            });
         }
         return Ok(peers_out);
     }
@@ -26,6 +26,104 @@ pub(super) fn resolve_peers(peers: &PeersConfig, paths: &Paths) -> Result<Vec<Pe
     resolve_count_peers(peers.count.unwrap_or(0), paths)
 }
 
+/// Maps a peer ID or a name from `PeersConfig::names` to an existing peer ID.
+pub(super) fn find_peer(query: &str, peers: &PeersConfig, paths: &Paths) -> Result<String> {
+    let existing = list_peer_dirs(paths)?;
+    if existing.iter().any(|id| id == query) {
+        return Ok(query.to_string());
+    }
+
+    let named = named_peers(peers);
+    let by_name = named
+        .iter()
+        .enumerate()
+        .find(|(_, (name, _))| name == query)
+        .or_else(|| {
+            let slug = slugify(query);
+            named
+                .iter()
+                .enumerate()
+                .find(|(_, (name, _))| !slug.is_empty() && slugify(name) == slug)
+        })
+        .map(|(idx, (name, _))| peer_id_for_name(idx, name));
+    if let Some(id) = by_name
+        && existing.contains(&id)
+    {
+        return Ok(id);
+    }
+
+    let mut known = existing;
+    known.sort();
+    if known.is_empty() {
+        anyhow::bail!("unknown peer {query}: no peers generated yet; run `rust-wg generate` first");
+    }
+    anyhow::bail!("unknown peer {query}; known peers: {}", known.join(", "))
+}
+
+/// Peer directories under `paths.peers`, sorted, each with the configured
+/// name it was generated for (if it still is).
+pub(super) fn generated_peers(
+    peers: &PeersConfig,
+    paths: &Paths,
+) -> Result<Vec<(String, Option<String>)>> {
+    let names: Vec<(String, String)> = named_peers(peers)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (name, _))| (peer_id_for_name(idx, &name), name))
+        .collect();
+    let mut ids = list_peer_dirs(paths)?;
+    ids.sort();
+    Ok(ids
+        .into_iter()
+        .map(|id| {
+            let name = names
+                .iter()
+                .find(|(named_id, _)| *named_id == id)
+                .map(|(_, name)| name.clone());
+            (id, name)
+        })
+        .collect())
+}
+
+/// How many peers `resolve_peers` yields for `peers`.
+pub(super) fn configured_count(peers: &PeersConfig) -> usize {
+    match named_peers(peers).len() {
+        0 => peers.count.unwrap_or(0),
+        named => named,
+    }
+}
+
+/// `[[peers.peer]]` entries first, then any `names` not already covered by an
+/// entry (matched by slug), which get default settings.
+fn named_peers(peers: &PeersConfig) -> Vec<(String, PeerSettings)> {
+    let mut named: Vec<(String, PeerSettings)> = peers
+        .peer
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.settings.clone()))
+        .collect();
+    let entry_slugs: HashSet<String> = peers
+        .peer
+        .iter()
+        .map(|entry| slugify(&entry.name))
+        .filter(|slug| !slug.is_empty())
+        .collect();
+    for name in peers.names.iter().flatten() {
+        if !entry_slugs.contains(&slugify(name)) {
+            named.push((name.clone(), PeerSettings::default()));
+        }
+    }
+    named
+}
+
+fn peer_id_for_name(idx: usize, name: &str) -> String {
+    let slug = slugify(name);
+    if slug.is_empty() {
+        format!("peer-unnamed-{}", idx + 1)
+    } else {
+        format!("peer-{slug}")
+    }
+}
+
 fn resolve_count_peers(count: usize, paths: &Paths) -> Result<Vec<Peer>> {
     if count == 0 {
         return Ok(Vec::new());
@@ -36,18 +134,26 @@ fn resolve_count_peers(count: usize, paths: &Paths) -> Result<Vec<Peer>> {
     let mut seen = HashSet::new();
     for id in existing.into_iter().take(count) {
         if seen.insert(id.clone()) {
-            peers_out.push(Peer { id });
+            peers_out.push(count_peer(id));
         }
     }
     while peers_out.len() < count {
         let id = format!("peer-{}", Uuid::new_v4());
         if seen.insert(id.clone()) {
-            peers_out.push(Peer { id });
+            peers_out.push(count_peer(id));
         }
     }
     Ok(peers_out)
 }
 
+fn count_peer(id: String) -> Peer {
+    Peer {
+        id,
+        name: None,
+        settings: PeerSettings::default(),
+    }
+}
+
 fn list_peer_dirs(paths: &Paths) -> Result<Vec<String>> {
     let mut peers = Vec::new();
     if !paths.peers.exists() {
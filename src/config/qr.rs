@@ -3,7 +3,7 @@ use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub(super) fn print_qr(conf_path: &Path) -> Result<()> {
+pub fn print_qr(conf_path: &Path) -> Result<()> {
     let format = if std::io::stdout().is_terminal() {
         "ansiutf8"
     } else {
@@ -0,0 +1,172 @@
+use crate::config::files::Files;
+use crate::config::io::{now_secs, read_to_string, write_atomic};
+use crate::config::leases;
+use crate::config::types::{Paths, Peer};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Addresses of revoked peers stay reserved this long unless configured.
+pub(super) const DEFAULT_QUARANTINE_HOURS: u64 = 24 * 7;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RevokedPeer {
+    pub peer_id: String,
+    pub public_key: String,
+    pub addresses: Vec<String>,
+    pub revoked_at: u64,
+    pub archive: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct RevocationState {
+    revoked: Vec<RevokedPeer>,
+}
+
+/// A peer about to be revoked: what `revoke` will record, read before
+/// anything changes so that it can be taken off the device first.
+pub struct PendingRevocation {
+    pub interface: String,
+    pub peer_id: String,
+    pub public_key: String,
+    pub addresses: Vec<String>,
+    paths: Paths,
+}
+
+/// Reads the public key and leased addresses of `peer_id` on `interface`.
+pub(super) fn pending(
+    interface: String,
+    paths: Paths,
+    peer_id: String,
+) -> Result<PendingRevocation> {
+    let public_key = read_to_string(paths.peers.join(&peer_id).join("public.key"))?
+        .trim()
+        .to_string();
+    let leases = leases::load(&paths, &mut Files::disk())?;
+    let addresses = leases
+        .iter()
+        .filter(|lease| lease.peer_id == peer_id)
+        .flat_map(|lease| lease.addresses())
+        .map(|addr| addr.to_string())
+        .collect();
+    Ok(PendingRevocation {
+        interface,
+        peer_id,
+        public_key,
+        addresses,
+        paths,
+    })
+}
+
+/// Moves the peer's directory out of `peers/` (into `revoked/`, or overwrites
+/// and deletes its key material) and records the revocation.
+pub(super) fn revoke(pending: &PendingRevocation, delete_keys: bool) -> Result<RevokedPeer> {
+    let paths = &pending.paths;
+    let peer_id = pending.peer_id.as_str();
+    let peer_dir = paths.peers.join(peer_id);
+    let mut files = Files::disk();
+    let mut leases = leases::load(paths, &mut files)?;
+    let revoked_at = now_secs();
+
+    let archive = if delete_keys {
+        shred_dir(&peer_dir)?;
+        None
+    } else {
+        let target = paths.revoked.join(format!("{peer_id}-{revoked_at}"));
+        fs::rename(&peer_dir, &target)
+            .with_context(|| format!("moving {} -> {}", peer_dir.display(), target.display()))?;
+        Some(target)
+    };
+
+    let record = RevokedPeer {
+        peer_id: peer_id.to_string(),
+        public_key: pending.public_key.clone(),
+        addresses: pending.addresses.clone(),
+        revoked_at,
+        archive,
+    };
+    let mut state = load_state(paths)?;
+    state.revoked.push(record.clone());
+    let text = serde_json::to_string_pretty(&state).context("serializing revoked.json")?;
+    write_atomic(&state_path(paths), text.as_bytes())?;
+    leases.retain(|lease| lease.peer_id != peer_id);
+    leases::save(paths, &leases, &mut files)?;
+    Ok(record)
+}
+
+/// Fails if any peer still holds key material that was revoked earlier, e.g.
+/// after an archived directory was copied back into `peers/`.
+pub(super) fn ensure_not_revoked(paths: &Paths, peers: &[Peer]) -> Result<()> {
+    let state = load_state(paths)?;
+    if state.revoked.is_empty() {
+        return Ok(());
+    }
+    for peer in peers {
+        let public_path = paths.peers.join(&peer.id).join("public.key");
+        if !public_path.exists() {
+            continue;
+        }
+        let public_key = read_to_string(&public_path)?;
+        if let Some(record) = state
+            .revoked
+            .iter()
+            .find(|record| record.public_key == public_key.trim())
+        {
+            anyhow::bail!(
+                "peer {} uses the key revoked from {}; delete {} to issue new keys",
+                peer.id,
+                record.peer_id,
+                paths.peers.join(&peer.id).display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Addresses of peers revoked less than `quarantine_hours` ago.
+pub(super) fn quarantined_addresses(paths: &Paths, quarantine_hours: u64) -> Result<Vec<String>> {
+    let cutoff = now_secs().saturating_sub(quarantine_hours.saturating_mul(3600));
+    Ok(load_state(paths)?
+        .revoked
+        .into_iter()
+        .filter(|record| record.revoked_at > cutoff)
+        .flat_map(|record| record.addresses)
+        .filter_map(|addr| addr.split('/').next().map(str::to_string))
+        .collect())
+}
+
+fn load_state(paths: &Paths) -> Result<RevocationState> {
+    let path = state_path(paths);
+    if !path.exists() {
+        return Ok(RevocationState::default());
+    }
+    serde_json::from_str(&read_to_string(&path)?).context("parsing revoked.json")
+}
+
+fn state_path(paths: &Paths) -> PathBuf {
+    paths.state.join("revoked.json")
+}
+
+/// Best-effort overwrite of every file before removing the directory; this
+/// does not defeat copy-on-write filesystems or snapshots.
+fn shred_dir(dir: &Path) -> Result<()> {
+    use std::io::Write;
+
+    for entry in fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let len = fs::metadata(&path)?.len() as usize;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        file.write_all(&vec![0; len])
+            .with_context(|| format!("overwriting {}", path.display()))?;
+        file.sync_all()
+            .with_context(|| format!("syncing {}", path.display()))?;
+    }
+    fs::remove_dir_all(dir).with_context(|| format!("removing {}", dir.display()))
+}
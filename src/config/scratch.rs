@@ -0,0 +1,38 @@
+use crate::config::Locations;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory of its own for one test under the system temp dir, removed
+/// on drop so that a failing test does not leave it behind.
+pub(super) struct ScratchDir {
+    root: PathBuf,
+}
+
+impl ScratchDir {
+    pub(super) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("rust-wg-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Writes `config` to `wg.toml` and points the state dir at `state/`.
+    pub(super) fn locations(&self, config: &str) -> Locations {
+        let path = self.root.join("wg.toml");
+        fs::write(&path, config).unwrap();
+        Locations {
+            config: Some(path),
+            state_dir: Some(self.root.join("state")),
+        }
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -1,5 +1,12 @@
+use crate::config::leases::Lease;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Interface name used when no `[[interface]]` is configured.
+pub const DEFAULT_INTERFACE: &str = "wg0";
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct ConfigFile {
@@ -11,6 +18,61 @@ pub struct ConfigFile {
     pub peers: PeersConfig,
     #[serde(default)]
     pub runtime: RuntimeConfigFile,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default, rename = "forward", skip_serializing_if = "Vec::is_empty")]
+    pub forwards: Vec<PortForward>,
+    #[serde(default, rename = "interface", skip_serializing_if = "Vec::is_empty")]
+    pub interfaces: Vec<InterfaceConfig>,
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct PathsConfig {
+    /// Root of keys, peer configs and state (default `/var/lib/wg`).
+    pub state_dir: Option<PathBuf>,
+}
+
+/// The Prometheus endpoint of `rust-wg run`, disabled unless `listen` is set.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct MetricsConfig {
+    /// Address to serve `/metrics` on, e.g. `127.0.0.1:9586`.
+    pub listen: Option<String>,
+}
+
+/// Notifications `rust-wg run` sends when a peer connects, disconnects or
+/// roams to a new endpoint. Events are always logged.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct EventsConfig {
+    /// Commands run per event, as argument lists, with the event in
+    /// `WG_EVENT`, `WG_INTERFACE` and `WG_PEER_*` variables.
+    #[serde(default)]
+    pub hooks: Vec<Vec<String>>,
+    /// `http://` URL the event is POSTed to as JSON.
+    pub webhook: Option<String>,
+    /// How often device state is polled (default 10 seconds).
+    pub poll_interval_secs: Option<u64>,
+    /// Age after which a handshake counts as stale (default 180 seconds,
+    /// when WireGuard itself drops the session).
+    pub stale_after_secs: Option<u64>,
+}
+
+/// An `[[interface]]` table: one WireGuard interface served by the same
+/// `rust-wg run`. Sections it leaves out are taken whole from the top level.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InterfaceConfig {
+    pub name: String,
+    pub server: Option<ServerConfig>,
+    pub network: Option<NetworkConfig>,
+    pub peers: Option<PeersConfig>,
+    pub acl: Option<AclConfig>,
+    #[serde(rename = "forward")]
+    pub forwards: Option<Vec<PortForward>>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -34,6 +96,62 @@ pub struct NetworkConfig {
     pub subnet_v6: Option<String>,
     pub allowed_ips: Vec<String>,
     pub peer_dns: Vec<String>,
+    /// Assign the server address with the subnet's prefix length instead of
+    /// /32 and /128, so one connected route covers every peer.
+    #[serde(default)]
+    pub assign_subnet_prefix: bool,
+    /// Whether peers may open connections to each other through the server.
+    #[serde(default)]
+    pub peer_to_peer: Policy,
+    /// Interfaces NAT and port forwards apply to; by default, those of the
+    /// default routes.
+    pub egress_interfaces: Option<Vec<String>>,
+    #[serde(default)]
+    pub nat: NatMode,
+    /// IPv6 override of `nat`, e.g. `routed` for a delegated global prefix.
+    pub nat_v6: Option<NatMode>,
+    /// Fixed source addresses to SNAT to instead of masquerading.
+    pub snat_v4: Option<String>,
+    pub snat_v6: Option<String>,
+    /// Prefix `subnet_v6` is translated to when `nat_v6 = "npt"`.
+    pub npt_prefix_v6: Option<String>,
+    /// MTU of wg0 (kernel default when unset), and the default `MTU` in
+    /// client configs.
+    pub mtu: Option<u16>,
+}
+
+/// How traffic from peers leaves through the egress interfaces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NatMode {
+    /// Rewrite the source to the egress address (or `snat_v4`/`snat_v6`).
+    #[default]
+    Masquerade,
+    /// Forward without source NAT; the upstream network routes the subnets
+    /// back to this host.
+    Routed,
+    /// IPv6 only: map `subnet_v6` onto `npt_prefix_v6` one-to-one, keeping
+    /// the interface identifier.
+    Npt,
+}
+
+impl NetworkConfig {
+    /// The NAT mode for IPv6 (`nat_v6`, else `nat`) or IPv4.
+    pub fn nat_mode(&self, v6: bool) -> NatMode {
+        if v6 {
+            self.nat_v6.unwrap_or(self.nat)
+        } else {
+            self.nat
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    #[default]
+    Allow,
+    Deny,
 }
 
 impl Default for NetworkConfig {
@@ -43,6 +161,15 @@ impl Default for NetworkConfig {
             subnet_v6: None,
             allowed_ips: vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
             peer_dns: Vec::new(),
+            assign_subnet_prefix: false,
+            peer_to_peer: Policy::Allow,
+            egress_interfaces: None,
+            nat: NatMode::Masquerade,
+            nat_v6: None,
+            snat_v4: None,
+            snat_v6: None,
+            npt_prefix_v6: None,
+            mtu: None,
         }
     }
 }
@@ -51,6 +178,95 @@ impl Default for NetworkConfig {
 pub struct PeersConfig {
     pub count: Option<usize>,
     pub names: Option<Vec<String>>,
+    /// Hours a revoked peer's addresses stay reserved (default one week).
+    pub revoke_quarantine_hours: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peer: Vec<PeerEntry>,
+}
+
+/// A `[[peers.peer]]` table: a named peer with optional per-peer overrides.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PeerEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: PeerSettings,
+}
+
+/// Per-peer overrides; unset fields fall back to `NetworkConfig`.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct PeerSettings {
+    pub address_v4: Option<String>,
+    pub address_v6: Option<String>,
+    pub allowed_ips: Option<Vec<String>>,
+    pub dns: Option<Vec<String>>,
+    pub persistent_keepalive: Option<u16>,
+    pub mtu: Option<u16>,
+    pub description: Option<String>,
+    pub routed_prefixes: Option<Vec<String>>,
+    pub peer_to_peer: Option<Policy>,
+    pub groups: Option<Vec<String>>,
+    pub acl: Option<AclRules>,
+}
+
+/// Firewall rules for traffic peers send through the server.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct AclConfig {
+    /// Verdict for traffic no rule matches, unless a peer or group overrides it.
+    #[serde(default)]
+    pub default: Policy,
+    #[serde(default)]
+    pub groups: BTreeMap<String, AclRules>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct AclRules {
+    pub default: Option<Policy>,
+    #[serde(default)]
+    pub allow: Vec<AclEntry>,
+    #[serde(default)]
+    pub deny: Vec<AclEntry>,
+}
+
+/// A destination, optionally narrowed to a protocol and ports; with ports but
+/// no protocol, both TCP and UDP match.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AclEntry {
+    pub dest: Option<String>,
+    pub proto: Option<Protocol>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+/// A `[[forward]]` entry: DNAT `external_port` on the container to
+/// `peer_port` (default: the same port) on a peer's leased address.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PortForward {
+    pub proto: Protocol,
+    pub external_port: u16,
+    pub peer: String,
+    pub peer_port: Option<u16>,
+}
+
+impl PortForward {
+    pub fn peer_port(&self) -> u16 {
+        self.peer_port.unwrap_or(self.external_port)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -69,16 +285,69 @@ impl Default for RuntimeConfigFile {
 #[derive(Debug, Clone)]
 pub struct Peer {
     pub id: String,
+    /// The configured name; `None` for peers created from `count`.
+    pub name: Option<String>,
+    pub settings: PeerSettings,
+}
+
+impl Peer {
+    pub fn allowed_ips<'a>(&'a self, network: &'a NetworkConfig) -> &'a [String] {
+        self.settings
+            .allowed_ips
+            .as_deref()
+            .unwrap_or(&network.allowed_ips)
+    }
+
+    pub fn dns<'a>(&'a self, network: &'a NetworkConfig) -> &'a [String] {
+        self.settings.dns.as_deref().unwrap_or(&network.peer_dns)
+    }
+
+    pub fn mtu(&self, network: &NetworkConfig) -> Option<u16> {
+        self.settings.mtu.or(network.mtu)
+    }
+
+    /// Whether this peer may open connections to other peers.
+    pub fn peer_to_peer(&self, network: &NetworkConfig) -> Policy {
+        self.settings.peer_to_peer.unwrap_or(network.peer_to_peer)
+    }
+
+    /// Networks behind this peer, routed to it in addition to its own address.
+    pub fn routed_prefixes(&self) -> Result<Vec<IpNet>> {
+        self.settings
+            .routed_prefixes
+            .iter()
+            .flatten()
+            .map(|prefix| {
+                prefix
+                    .parse::<IpNet>()
+                    .map(|net| net.trunc())
+                    .with_context(|| format!("parsing routed prefix {prefix} for {}", self.id))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ResolvedConfig {
+    /// Name of the WireGuard interface this configuration is for.
+    pub interface: String,
     pub server: ServerConfig,
     pub network: NetworkConfig,
     pub peers: Vec<Peer>,
+    pub acl: AclConfig,
+    /// Port forwards, with `peer` resolved to a peer ID.
+    pub forwards: Vec<PortForward>,
+    /// Leases of `peers`; the source of truth for their addresses.
+    pub leases: Vec<Lease>,
     pub paths: Paths,
 }
 
+impl ResolvedConfig {
+    pub fn lease(&self, peer_id: &str) -> Option<&Lease> {
+        self.leases.iter().find(|lease| lease.peer_id == peer_id)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Paths {
     pub root: PathBuf,
@@ -86,6 +355,20 @@ pub struct Paths {
     pub peers: PathBuf,
     pub server: PathBuf,
     pub state: PathBuf,
+    pub revoked: PathBuf,
+}
+
+impl Paths {
+    pub(super) fn under(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            keys: root.join("keys"),
+            peers: root.join("peers"),
+            server: root.join("server"),
+            state: root.join("state"),
+            revoked: root.join("revoked"),
+        }
+    }
 }
 
 pub(super) struct KeyPair {
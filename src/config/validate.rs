@@ -0,0 +1,758 @@
+use crate::config::env;
+use crate::config::peers;
+use crate::config::types::{ConfigFile, NatMode, NetworkConfig, PeersConfig};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+use std::path::Path;
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+/// One thing wrong with the config file or an environment override.
+#[derive(Debug)]
+pub struct Problem {
+    /// `<file>:<line>:<column>`, or the environment variable.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// What a config key or environment override holds.
+#[derive(Clone, Copy)]
+pub(super) enum Kind {
+    Str,
+    Bool,
+    U16,
+    U64,
+    Usize,
+    Ipv4,
+    Ipv6,
+    Ip,
+    /// An IPv4 CIDR, e.g. `subnet_v4`.
+    Net4,
+    /// An IPv6 CIDR, e.g. `subnet_v6`.
+    Net6,
+    /// A CIDR of either family.
+    Cidr,
+    /// A CIDR or a bare address.
+    Net,
+    /// `address:port`.
+    Listen,
+    OneOf(&'static [&'static str]),
+    List(&'static Kind),
+    Table(&'static [Key]),
+    /// An array of tables, e.g. `[[forward]]`.
+    Tables(&'static [Key]),
+    /// A table of tables under names of the user's choosing.
+    Map(&'static [Key]),
+}
+
+pub(super) struct Key {
+    pub(super) name: &'static str,
+    pub(super) kind: Kind,
+    required: bool,
+}
+
+const fn opt(name: &'static str, kind: Kind) -> Key {
+    Key {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+const fn req(name: &'static str, kind: Kind) -> Key {
+    Key {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+pub(super) const POLICY: Kind = Kind::OneOf(&["allow", "deny"]);
+pub(super) const NAT: Kind = Kind::OneOf(&["masquerade", "routed", "npt"]);
+const PROTOCOL: Kind = Kind::OneOf(&["tcp", "udp"]);
+
+// The keys of `types::ConfigFile` and the structs below it.
+pub(super) const TOP: &[Key] = &[
+    opt("server", Kind::Table(SERVER)),
+    opt("network", Kind::Table(NETWORK)),
+    opt("peers", Kind::Table(PEERS)),
+    opt("runtime", Kind::Table(RUNTIME)),
+    opt("acl", Kind::Table(ACL)),
+    opt("forward", Kind::Tables(FORWARD)),
+    opt("interface", Kind::Tables(INTERFACE)),
+    opt("paths", Kind::Table(PATHS)),
+    opt("metrics", Kind::Table(METRICS)),
+    opt("events", Kind::Table(EVENTS)),
+];
+const INTERFACE: &[Key] = &[
+    req("name", Kind::Str),
+    opt("server", Kind::Table(SERVER)),
+    opt("network", Kind::Table(NETWORK)),
+    opt("peers", Kind::Table(PEERS)),
+    opt("acl", Kind::Table(ACL)),
+    opt("forward", Kind::Tables(FORWARD)),
+];
+const SERVER: &[Key] = &[
+    req("listen_port", Kind::U16),
+    opt("external_address", Kind::Str),
+];
+const NETWORK: &[Key] = &[
+    req("subnet_v4", Kind::Net4),
+    opt("subnet_v6", Kind::Net6),
+    req("allowed_ips", Kind::List(&Kind::Net)),
+    req("peer_dns", Kind::List(&Kind::Ip)),
+    opt("assign_subnet_prefix", Kind::Bool),
+    opt("peer_to_peer", POLICY),
+    opt("egress_interfaces", Kind::List(&Kind::Str)),
+    opt("nat", NAT),
+    opt("nat_v6", NAT),
+    opt("snat_v4", Kind::Ipv4),
+    opt("snat_v6", Kind::Ipv6),
+    opt("npt_prefix_v6", Kind::Net6),
+    opt("mtu", Kind::U16),
+];
+const PEERS: &[Key] = &[
+    opt("count", Kind::Usize),
+    opt("names", Kind::List(&Kind::Str)),
+    opt("revoke_quarantine_hours", Kind::U64),
+    opt("peer", Kind::Tables(PEER)),
+];
+const PEER: &[Key] = &[
+    req("name", Kind::Str),
+    opt("address_v4", Kind::Ipv4),
+    opt("address_v6", Kind::Ipv6),
+    opt("allowed_ips", Kind::List(&Kind::Net)),
+    opt("dns", Kind::List(&Kind::Ip)),
+    opt("persistent_keepalive", Kind::U16),
+    opt("mtu", Kind::U16),
+    opt("description", Kind::Str),
+    opt("routed_prefixes", Kind::List(&Kind::Cidr)),
+    opt("peer_to_peer", POLICY),
+    opt("groups", Kind::List(&Kind::Str)),
+    opt("acl", Kind::Table(ACL_RULES)),
+];
+const ACL: &[Key] = &[opt("default", POLICY), opt("groups", Kind::Map(ACL_RULES))];
+const ACL_RULES: &[Key] = &[
+    opt("default", POLICY),
+    opt("allow", Kind::Tables(ACL_ENTRY)),
+    opt("deny", Kind::Tables(ACL_ENTRY)),
+];
+const ACL_ENTRY: &[Key] = &[
+    opt("dest", Kind::Net),
+    opt("proto", PROTOCOL),
+    opt("ports", Kind::List(&Kind::U16)),
+];
+const FORWARD: &[Key] = &[
+    req("proto", PROTOCOL),
+    req("external_port", Kind::U16),
+    req("peer", Kind::Str),
+    opt("peer_port", Kind::U16),
+];
+const RUNTIME: &[Key] = &[req("emit_qr", Kind::Bool)];
+const PATHS: &[Key] = &[opt("state_dir", Kind::Str)];
+const METRICS: &[Key] = &[opt("listen", Kind::Listen)];
+const EVENTS: &[Key] = &[
+    opt("hooks", Kind::List(&Kind::List(&Kind::Str))),
+    opt("webhook", Kind::Str),
+    opt("poll_interval_secs", Kind::U64),
+    opt("stale_after_secs", Kind::U64),
+];
+
+/// A config file that passed [`parse`].
+pub(super) struct Parsed {
+    /// With the environment overrides applied.
+    pub(super) cfg: ConfigFile,
+    /// `<file>:<line>:<column>` of every value set in the file, by dotted
+    /// path such as `interface[0].network.subnet_v4`.
+    pub(super) found: HashMap<String, String>,
+}
+
+/// Checks `text`, read from `path`, and the environment overrides in one
+/// pass, and only returns the config if nothing at all is wrong.
+pub(super) fn parse(path: &Path, text: &str) -> Result<Parsed, Vec<Problem>> {
+    let mut checker = Checker {
+        path,
+        text,
+        problems: Vec::new(),
+        spans: HashMap::new(),
+    };
+    let (document, errors) = DeTable::parse_recoverable(text);
+    for err in &errors {
+        checker.report(err.span().map_or(0, |span| span.start), err.message());
+    }
+    if errors.is_empty() {
+        checker.table("", document.span(), document.get_ref(), TOP);
+    }
+    // The network checks need the whole config, which may still deserialize
+    // with unknown keys or bad strings in it.
+    let cfg = match toml::from_str::<ConfigFile>(text) {
+        Ok(mut cfg) => {
+            env::apply_env_overrides(&mut cfg);
+            checker.networks(&cfg);
+            Some(cfg)
+        }
+        Err(err) => {
+            if checker.problems.is_empty() {
+                checker.report(err.span().map_or(0, |span| span.start), err.message());
+            }
+            None
+        }
+    };
+    checker
+        .problems
+        .sort_by_key(|(offset, _)| offset.unwrap_or(usize::MAX));
+    checker
+        .problems
+        .dedup_by(|a, b| a.0 == b.0 && a.1.message == b.1.message);
+    for (var, message) in env::invalid_overrides() {
+        checker.problems.push((
+            None,
+            Problem {
+                location: var.to_string(),
+                message,
+            },
+        ));
+    }
+    match cfg {
+        Some(cfg) if checker.problems.is_empty() => {
+            let found = checker
+                .spans
+                .iter()
+                .map(|(key, span)| (key.clone(), checker.location(span.start)))
+                .collect();
+            Ok(Parsed { cfg, found })
+        }
+        _ => Err(checker.into_problems()),
+    }
+}
+
+/// Checks one string value, as found in the file or the environment.
+pub(super) fn check_str(kind: Kind, value: &str) -> Result<(), String> {
+    let ok = match kind {
+        Kind::Str => true,
+        Kind::Bool => matches!(
+            value.to_ascii_lowercase().as_str(),
+            "true" | "1" | "yes" | "false" | "0" | "no"
+        ),
+        Kind::U16 => value.parse::<u16>().is_ok(),
+        Kind::U64 => value.parse::<u64>().is_ok(),
+        Kind::Usize => value.parse::<usize>().is_ok(),
+        Kind::Ipv4 => value.parse::<Ipv4Addr>().is_ok(),
+        Kind::Ipv6 => value.parse::<Ipv6Addr>().is_ok(),
+        Kind::Ip => value.parse::<IpAddr>().is_ok(),
+        Kind::Net4 => value.parse::<Ipv4Net>().is_ok(),
+        Kind::Net6 => value.parse::<Ipv6Net>().is_ok(),
+        Kind::Cidr => value.parse::<IpNet>().is_ok(),
+        Kind::Net => value.parse::<IpNet>().is_ok() || value.parse::<IpAddr>().is_ok(),
+        Kind::Listen => value.parse::<SocketAddr>().is_ok(),
+        Kind::OneOf(options) => options.contains(&value),
+        Kind::List(_) | Kind::Table(_) | Kind::Tables(_) | Kind::Map(_) => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("`{value}` is not {}", describe(kind)))
+    }
+}
+
+fn describe(kind: Kind) -> String {
+    match kind {
+        Kind::Str => "a string".to_string(),
+        Kind::Bool => "true or false".to_string(),
+        Kind::U16 => "an integer from 0 to 65535".to_string(),
+        Kind::U64 | Kind::Usize => "a non-negative integer".to_string(),
+        Kind::Ipv4 => "an IPv4 address".to_string(),
+        Kind::Ipv6 => "an IPv6 address".to_string(),
+        Kind::Ip => "an IP address".to_string(),
+        Kind::Net4 => "an IPv4 CIDR like 10.66.0.0/24".to_string(),
+        Kind::Net6 => "an IPv6 CIDR like fd66::/64".to_string(),
+        Kind::Cidr => "a CIDR like 192.168.1.0/24".to_string(),
+        Kind::Net => "an address or CIDR".to_string(),
+        Kind::Listen => "an address:port like 127.0.0.1:9586".to_string(),
+        Kind::OneOf(options) => format!("one of {}", options.join(", ")),
+        Kind::List(_) => "an array".to_string(),
+        Kind::Table(_) | Kind::Map(_) => "a table".to_string(),
+        Kind::Tables(_) => "an array of tables".to_string(),
+    }
+}
+
+struct Checker<'a> {
+    path: &'a Path,
+    text: &'a str,
+    /// With the byte offset they point at, for ordering.
+    problems: Vec<(Option<usize>, Problem)>,
+    /// Where each value was found, by dotted key path.
+    spans: HashMap<String, Range<usize>>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, offset: usize, message: impl Into<String>) {
+        let location = self.location(offset);
+        self.problems.push((
+            Some(offset),
+            Problem {
+                location,
+                message: message.into(),
+            },
+        ));
+    }
+
+    fn location(&self, offset: usize) -> String {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+        format!("{}:{line}:{column}", self.path.display())
+    }
+
+    fn into_problems(self) -> Vec<Problem> {
+        self.problems
+            .into_iter()
+            .map(|(_, problem)| problem)
+            .collect()
+    }
+
+    fn table(&mut self, path: &str, span: Range<usize>, table: &DeTable<'_>, keys: &[Key]) {
+        for (name, value) in table.iter() {
+            let child = join(path, name.get_ref());
+            match keys.iter().find(|key| key.name == name.get_ref().as_ref()) {
+                Some(key) => self.value(&child, value, key.kind),
+                None => {
+                    let within = if path.is_empty() {
+                        "at the top level".to_string()
+                    } else {
+                        format!("in [{path}]")
+                    };
+                    self.report(
+                        name.span().start,
+                        format!("unknown key `{name}` {within}", name = name.get_ref()),
+                    );
+                }
+            }
+        }
+        for key in keys.iter().filter(|key| key.required) {
+            if table.get(key.name).is_none() {
+                self.report(span.start, format!("[{path}] is missing `{}`", key.name));
+            }
+        }
+    }
+
+    fn value(&mut self, path: &str, value: &Spanned<DeValue<'_>>, kind: Kind) {
+        let span = value.span();
+        self.spans.insert(path.to_string(), span.clone());
+        match (kind, value.get_ref()) {
+            (Kind::List(item), DeValue::Array(items)) => {
+                for (idx, value) in items.iter().enumerate() {
+                    self.value(&format!("{path}[{idx}]"), value, *item);
+                }
+            }
+            (Kind::Table(keys), DeValue::Table(table)) => self.table(path, span, table, keys),
+            (Kind::Tables(keys), DeValue::Array(items)) => {
+                for (idx, item) in items.iter().enumerate() {
+                    self.value(&format!("{path}[{idx}]"), item, Kind::Table(keys));
+                }
+            }
+            (Kind::Map(keys), DeValue::Table(table)) => {
+                for (name, value) in table.iter() {
+                    self.value(&join(path, name.get_ref()), value, Kind::Table(keys));
+                }
+            }
+            (Kind::Bool, DeValue::Boolean(_)) => {}
+            (Kind::U16 | Kind::U64 | Kind::Usize, DeValue::Integer(int)) => {
+                let parsed = i128::from_str_radix(int.as_str(), int.radix()).ok();
+                let max = match kind {
+                    Kind::U16 => u128::from(u16::MAX),
+                    _ => u128::from(u64::MAX),
+                };
+                if !parsed.is_some_and(|n| n >= 0 && n as u128 <= max) {
+                    self.report(
+                        span.start,
+                        format!("{path}: {int} is not {}", describe(kind)),
+                    );
+                }
+            }
+            (Kind::Bool | Kind::U16 | Kind::U64 | Kind::Usize, _)
+            | (Kind::List(_) | Kind::Table(_) | Kind::Tables(_) | Kind::Map(_), _) => {
+                self.mismatch(path, span.start, kind, value.get_ref());
+            }
+            (_, DeValue::String(text)) => {
+                if let Err(message) = check_str(kind, text) {
+                    self.report(span.start, format!("{path}: {message}"));
+                }
+            }
+            (_, other) => self.mismatch(path, span.start, kind, other),
+        }
+    }
+
+    fn mismatch(&mut self, path: &str, offset: usize, kind: Kind, found: &DeValue<'_>) {
+        self.report(
+            offset,
+            format!(
+                "{path}: expected {}, found {}",
+                describe(kind),
+                found.type_str()
+            ),
+        );
+    }
+
+    /// Every interface's subnets must hold the server and all its peers, and
+    /// its NAT settings must fit together.
+    fn networks(&mut self, cfg: &ConfigFile) {
+        if cfg.interfaces.is_empty() {
+            self.subnet_capacity("", &cfg.network, &cfg.peers);
+            self.npt("", &cfg.network);
+        }
+        for (idx, entry) in cfg.interfaces.iter().enumerate() {
+            let prefix = format!("interface[{idx}]");
+            let (scope, network) = match &entry.network {
+                Some(network) => (prefix.as_str(), network),
+                None => ("", &cfg.network),
+            };
+            self.subnet_capacity(scope, network, entry.peers.as_ref().unwrap_or(&cfg.peers));
+            self.npt(scope, network);
+        }
+    }
+
+    fn subnet_capacity(&mut self, scope: &str, network: &NetworkConfig, peers: &PeersConfig) {
+        let wanted = peers::configured_count(peers) as u128;
+        // Host addresses of each subnet, as the allocator hands them out.
+        let mut subnets = Vec::new();
+        if let Ok(net) = network.subnet_v4.parse::<Ipv4Net>() {
+            let hosts = 1u128 << (32 - net.prefix_len());
+            let hosts = if net.prefix_len() >= 31 {
+                hosts
+            } else {
+                hosts - 2
+            };
+            subnets.push((
+                "subnet_v4",
+                network.subnet_v4.as_str(),
+                hosts,
+                "WG_SUBNET_V4",
+            ));
+        }
+        if let Some(raw) = network.subnet_v6.as_deref()
+            && let Ok(net) = raw.parse::<Ipv6Net>()
+        {
+            let hosts = 1u128
+                .checked_shl(128 - u32::from(net.prefix_len()))
+                .unwrap_or(u128::MAX);
+            subnets.push(("subnet_v6", raw, hosts, "WG_SUBNET_V6"));
+        }
+        for (key, raw, hosts, var) in subnets {
+            let room = hosts.saturating_sub(1);
+            if wanted <= room {
+                continue;
+            }
+            let message = format!(
+                "{wanted} peers are configured but {raw} only fits {room} besides the server"
+            );
+            let from_env = scope.is_empty() && std::env::var(var).is_ok_and(|value| value == raw);
+            self.report_network(scope, key, from_env.then_some(var), message);
+        }
+    }
+
+    /// `npt` is IPv6-only and needs `subnet_v6` and an `npt_prefix_v6` of the
+    /// same length; `npt_prefix_v6` does nothing without it.
+    fn npt(&mut self, scope: &str, network: &NetworkConfig) {
+        // Overrides only replace the top-level `[network]`.
+        let overrides = if scope.is_empty() {
+            env::applied_overrides()
+        } else {
+            Vec::new()
+        };
+        let from_env = |key: &str| {
+            let path = format!("network.{key}");
+            overrides
+                .iter()
+                .find(|(overridden, _)| *overridden == path)
+                .map(|(_, var)| *var)
+        };
+        if network.nat == NatMode::Npt {
+            let message = "`npt` is IPv6-only; set nat_v6 = \"npt\" instead".to_string();
+            self.report_network(scope, "nat", from_env("nat"), message);
+        }
+        let prefix = network.npt_prefix_v6.as_deref();
+        if network.nat_mode(true) != NatMode::Npt {
+            if prefix.is_some() {
+                let message = "npt_prefix_v6 is set but nat_v6 is not \"npt\"".to_string();
+                let var = from_env("npt_prefix_v6");
+                self.report_network(scope, "npt_prefix_v6", var, message);
+            }
+            return;
+        }
+        let mode = if network.nat_v6.is_some() {
+            "nat_v6"
+        } else {
+            "nat"
+        };
+        let Some(subnet) = network.subnet_v6.as_deref() else {
+            let message = "`npt` requires subnet_v6".to_string();
+            self.report_network(scope, mode, from_env(mode), message);
+            return;
+        };
+        let Some(prefix) = prefix else {
+            let message = "`npt` requires npt_prefix_v6".to_string();
+            self.report_network(scope, mode, from_env(mode), message);
+            return;
+        };
+        // Unparseable values were reported by the schema walk.
+        if let (Ok(internal), Ok(external)) = (subnet.parse::<Ipv6Net>(), prefix.parse::<Ipv6Net>())
+            && internal.prefix_len() != external.prefix_len()
+        {
+            let message =
+                format!("{prefix} must have the same prefix length as subnet_v6 {subnet}");
+            let var = from_env("npt_prefix_v6");
+            self.report_network(scope, "npt_prefix_v6", var, message);
+        }
+    }
+
+    /// Reports `message` against `network.<key>` of `scope`: at the
+    /// environment variable that set it, where the file sets it, or else at
+    /// the top of the file.
+    fn report_network(&mut self, scope: &str, key: &str, var: Option<&str>, message: String) {
+        let path = join(scope, &format!("network.{key}"));
+        match (var, self.spans.get(&path).map(|span| span.start)) {
+            (Some(var), _) => self.problems.push((
+                None,
+                Problem {
+                    location: var.to_string(),
+                    message,
+                },
+            )),
+            (None, Some(offset)) => self.report(offset, format!("{path}: {message}")),
+            (None, None) => self.report(0, format!("{key}: {message}")),
+        }
+    }
+}
+
+pub(super) fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_problem_with_its_line() {
+        let text = "\
+[server]
+listen_port = 70000
+[network]
+subnet_v4 = \"10.66.0.0/30\"
+allowed_ips = [\"0.0.0.0/0\", \"10.3.0\"]
+peer_dns = [\"dns.example.com\"]
+nat = \"mask\"
+[peers]
+names = [\"a\", \"b\"]
+[[peers.peer]]
+name = \"c\"
+adress_v4 = \"10.66.0.2\"
+";
+        let problems = parse(Path::new("wg.toml"), text).err().unwrap();
+        let lines: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "wg.toml:2:15: server.listen_port: 70000 is not an integer from 0 to 65535",
+                "wg.toml:5:29: network.allowed_ips[1]: `10.3.0` is not an address or CIDR",
+                "wg.toml:6:13: network.peer_dns[0]: `dns.example.com` is not an IP address",
+                "wg.toml:7:7: network.nat: `mask` is not one of masquerade, routed, npt",
+                "wg.toml:12:1: unknown key `adress_v4` in [peers.peer[0]]",
+            ]
+        );
+
+        let small = "[network]\nsubnet_v4 = \"10.66.0.0/30\"\nallowed_ips = []\npeer_dns = []\n\
+                     [peers]\ncount = 2\n";
+        let problems = parse(Path::new("wg.toml"), small).err().unwrap();
+        assert_eq!(
+            problems[0].to_string(),
+            "wg.toml:2:13: network.subnet_v4: 2 peers are configured but 10.66.0.0/30 only \
+             fits 1 besides the server"
+        );
+    }
+
+    /// Records the paths of schema keys that `found` lacks, taking the first
+    /// entry of arrays of tables and the `ops` entry of maps.
+    fn unfound(found: &HashMap<String, String>, path: &str, keys: &[Key], out: &mut Vec<String>) {
+        for key in keys {
+            let child = join(path, key.name);
+            match key.kind {
+                Kind::Table(keys) => unfound(found, &child, keys, out),
+                Kind::Tables(keys) => unfound(found, &format!("{child}[0]"), keys, out),
+                Kind::Map(keys) => unfound(found, &join(&child, "ops"), keys, out),
+                _ if !found.contains_key(&child) => out.push(child),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn checks_npt_settings_per_interface() {
+        let text = "\
+[network]
+subnet_v4 = \"10.66.0.0/24\"
+allowed_ips = []
+peer_dns = []
+subnet_v6 = \"fd66::/64\"
+nat = \"npt\"
+npt_prefix_v6 = \"2001:db8:1::/56\"
+[[interface]]
+name = \"wg0\"
+[[interface]]
+name = \"wg1\"
+[interface.network]
+subnet_v4 = \"10.67.0.0/24\"
+allowed_ips = []
+peer_dns = []
+npt_prefix_v6 = \"2001:db8:2::/64\"
+[[interface]]
+name = \"wg2\"
+[interface.network]
+subnet_v4 = \"10.68.0.0/24\"
+allowed_ips = []
+peer_dns = []
+subnet_v6 = \"fd68::/64\"
+nat_v6 = \"npt\"
+";
+        let problems = parse(Path::new("wg.toml"), text).err().unwrap();
+        let lines: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "wg.toml:6:7: network.nat: `npt` is IPv6-only; set nat_v6 = \"npt\" instead",
+                "wg.toml:7:17: network.npt_prefix_v6: 2001:db8:1::/56 must have the same \
+                 prefix length as subnet_v6 fd66::/64",
+                "wg.toml:16:17: interface[1].network.npt_prefix_v6: npt_prefix_v6 is set but \
+                 nat_v6 is not \"npt\"",
+                "wg.toml:24:10: interface[2].network.nat_v6: `npt` requires npt_prefix_v6",
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_matches_the_config_types() {
+        use crate::config::types::*;
+        use std::collections::BTreeMap;
+
+        let strings = |items: &[&str]| items.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let entry = AclEntry {
+            dest: Some("10.3.0.21".to_string()),
+            proto: Some(Protocol::Tcp),
+            ports: vec![22],
+        };
+        let rules = AclRules {
+            default: Some(Policy::Deny),
+            allow: vec![entry.clone()],
+            deny: vec![entry],
+        };
+        let acl = AclConfig {
+            default: Policy::Deny,
+            groups: BTreeMap::from([("ops".to_string(), rules.clone())]),
+        };
+        let server = |listen_port| ServerConfig {
+            listen_port,
+            external_address: Some("vpn.example.com".to_string()),
+        };
+        let network = |v4: &str, v6: &str| NetworkConfig {
+            subnet_v4: v4.to_string(),
+            subnet_v6: Some(v6.to_string()),
+            allowed_ips: strings(&["0.0.0.0/0", "::/0"]),
+            peer_dns: strings(&["10.66.0.1"]),
+            assign_subnet_prefix: true,
+            peer_to_peer: Policy::Deny,
+            egress_interfaces: Some(strings(&["eth0"])),
+            nat: NatMode::Masquerade,
+            nat_v6: Some(NatMode::Npt),
+            snat_v4: Some("192.0.2.1".to_string()),
+            snat_v6: Some("2001:db8::1".to_string()),
+            npt_prefix_v6: Some("2001:db8:1::/64".to_string()),
+            mtu: Some(1420),
+        };
+        let peers = PeersConfig {
+            count: Some(1),
+            names: Some(strings(&["phone"])),
+            revoke_quarantine_hours: Some(24),
+            peer: vec![PeerEntry {
+                name: "phone".to_string(),
+                settings: PeerSettings {
+                    address_v4: Some("10.66.0.9".to_string()),
+                    address_v6: Some("fd66::9".to_string()),
+                    allowed_ips: Some(strings(&["10.3.0.0/16"])),
+                    dns: Some(Vec::new()),
+                    persistent_keepalive: Some(25),
+                    mtu: Some(1280),
+                    description: Some("phone".to_string()),
+                    routed_prefixes: Some(strings(&["192.168.50.0/24"])),
+                    peer_to_peer: Some(Policy::Allow),
+                    groups: Some(strings(&["ops"])),
+                    acl: Some(rules),
+                },
+            }],
+        };
+        let forward = PortForward {
+            proto: Protocol::Tcp,
+            external_port: 2222,
+            peer: "phone".to_string(),
+            peer_port: Some(22),
+        };
+        let cfg = ConfigFile {
+            server: server(51820),
+            network: network("10.66.0.0/24", "fd66::/64"),
+            peers: peers.clone(),
+            runtime: RuntimeConfigFile { emit_qr: false },
+            acl: acl.clone(),
+            forwards: vec![forward.clone()],
+            interfaces: vec![InterfaceConfig {
+                name: "wg1".to_string(),
+                server: Some(server(51821)),
+                network: Some(network("10.67.0.0/24", "fd67::/64")),
+                peers: Some(peers),
+                acl: Some(acl),
+                forwards: Some(vec![forward]),
+            }],
+            paths: PathsConfig {
+                state_dir: Some("/var/lib/wg".into()),
+            },
+            metrics: MetricsConfig {
+                listen: Some("127.0.0.1:9586".to_string()),
+            },
+            events: EventsConfig {
+                hooks: vec![strings(&["/usr/local/bin/notify", "--quiet"])],
+                webhook: Some("http://127.0.0.1:8080/wg-events".to_string()),
+                poll_interval_secs: Some(10),
+                stale_after_secs: Some(180),
+            },
+        };
+
+        // A key only the types know is reported as unknown; one only the
+        // schema knows is never found.
+        let text = toml::to_string(&cfg).unwrap();
+        let parsed = match parse(Path::new("wg.toml"), &text) {
+            Ok(parsed) => parsed,
+            Err(problems) => panic!("{problems:#?}\n{text}"),
+        };
+        let mut missing = Vec::new();
+        unfound(&parsed.found, "", TOP, &mut missing);
+        assert!(missing.is_empty(), "not serialized: {missing:?}");
+    }
+}
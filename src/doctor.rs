@@ -0,0 +1,400 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{self, Locations, ResolvedConfig};
+use crate::module_check::{self, Missing};
+use crate::{netlink_util, nft, wg_iface};
+
+/// How long a lookup of `external_address` may take.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: &'static str,
+    detail: String,
+    /// What to change, for warnings and failures.
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Pass,
+            name,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            name,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: Option<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            name,
+            detail: detail.into(),
+            fix,
+        }
+    }
+}
+
+/// Checks the host and configuration for what `rust-wg run` needs and prints
+/// each result; fails if any check failed.
+pub async fn run(locations: &Locations) -> Result<()> {
+    let mut checks = vec![wireguard_support().await, nftables()];
+
+    let planned = config::plan(locations);
+    let configs: Vec<ResolvedConfig> = match &planned {
+        Ok(planned) => planned.iter().map(|(config, _)| config.clone()).collect(),
+        Err(_) => Vec::new(),
+    };
+    let ipv6 = configs
+        .iter()
+        .any(|config| config.network.subnet_v6.is_some());
+
+    checks.push(sysctl(
+        "ipv4 forwarding",
+        "net.ipv4.ip_forward",
+        Status::Fail,
+        "peers cannot reach anything past the server",
+        "set Sysctl=net.ipv4.conf.all.forwarding=1 in the quadlet, or run \
+         `sysctl -w net.ipv4.ip_forward=1`",
+    ));
+    if ipv6 {
+        checks.push(sysctl(
+            "ipv6 forwarding",
+            "net.ipv6.conf.all.forwarding",
+            Status::Fail,
+            "peers cannot reach anything past the server over IPv6",
+            "set Sysctl=net.ipv6.conf.all.forwarding=1 in the quadlet, or run \
+             `sysctl -w net.ipv6.conf.all.forwarding=1`",
+        ));
+    }
+    checks.push(sysctl(
+        "src_valid_mark",
+        "net.ipv4.conf.all.src_valid_mark",
+        Status::Warn,
+        "reverse-path filtering may drop fwmark-routed replies",
+        "set Sysctl=net.ipv4.conf.all.src_valid_mark=1 in the quadlet",
+    ));
+    checks.push(default_route(false, &configs).await);
+    if ipv6 {
+        checks.push(default_route(true, &configs).await);
+    }
+
+    match planned {
+        Ok(planned) => {
+            let summary: Vec<String> = planned
+                .iter()
+                .map(|(config, _)| format!("{}: {} peers", config.interface, config.peers.len()))
+                .collect();
+            checks.push(Check::pass("config", summary.join(", ")));
+            checks.push(match nft::plan(&configs).await {
+                Ok(tables) => Check::pass("nftables rules", format!("{} tables", tables.len())),
+                Err(err) => Check::fail("nftables rules", format!("{err:#}"), None),
+            });
+            checks.extend(listen_ports(&configs).await);
+            for config in &configs {
+                checks.push(external_address(config).await);
+                checks.push(state_dir(config));
+            }
+        }
+        Err(err) => checks.push(Check::fail(
+            "config",
+            format!("{err:#}"),
+            Some("fix the error above; the checks that need the config were skipped".to_string()),
+        )),
+    }
+
+    for check in &checks {
+        let status = match check.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        println!("{status}  {:<16} {}", check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("{:22}fix: {fix}", "");
+        }
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} checks failed", checks.len());
+    }
+    println!("doctor: none of {} checks failed", checks.len());
+    Ok(())
+}
+
+async fn wireguard_support() -> Check {
+    const NAME: &str = "wireguard";
+    match module_check::probe().await {
+        Ok(None) => Check::pass(NAME, "can create WireGuard interfaces"),
+        Ok(Some(Missing::Privileges)) => Check::fail(
+            NAME,
+            "missing CAP_NET_ADMIN",
+            Some("run as root, or add AddCapability=NET_ADMIN to the quadlet".to_string()),
+        ),
+        Ok(Some(Missing::Module)) => Check::fail(
+            NAME,
+            "kernel module not available",
+            Some(
+                "run `modprobe wireguard` on the host, or mount /lib/modules into the container"
+                    .to_string(),
+            ),
+        ),
+        Err(err) => Check::fail(NAME, format!("{err:#}"), None),
+    }
+}
+
+fn nftables() -> Check {
+    match nft::probe() {
+        Ok(()) => Check::pass("nftables", "nf_tables answers over netlink"),
+        Err(err) => Check::fail(
+            "nftables",
+            format!("{err:#}"),
+            Some(
+                "needs CAP_NET_ADMIN and the nf_tables module (`modprobe nf_tables` on the host); \
+                 the nft binary is not used"
+                    .to_string(),
+            ),
+        ),
+    }
+}
+
+fn sysctl(name: &'static str, key: &str, severity: Status, impact: &str, fix: &str) -> Check {
+    let path = Path::new("/proc/sys").join(key.replace('.', "/"));
+    sysctl_check(
+        name,
+        key,
+        fs::read_to_string(&path).ok().as_deref(),
+        severity,
+        impact,
+        fix,
+    )
+}
+
+fn sysctl_check(
+    name: &'static str,
+    key: &str,
+    value: Option<&str>,
+    severity: Status,
+    impact: &str,
+    fix: &str,
+) -> Check {
+    match value.map(str::trim) {
+        Some("1") => Check::pass(name, format!("{key} = 1")),
+        Some(value) => {
+            let detail = format!("{key} = {value}; {impact}");
+            match severity {
+                Status::Fail => Check::fail(name, detail, Some(fix.to_string())),
+                _ => Check::warn(name, detail, fix),
+            }
+        }
+        None => Check::warn(name, format!("cannot read {key}"), fix),
+    }
+}
+
+async fn default_route(v6: bool, configs: &[ResolvedConfig]) -> Check {
+    let (name, family) = if v6 {
+        ("ipv6 default", "ipv6")
+    } else {
+        ("ipv4 default", "ipv4")
+    };
+    let devs = async {
+        let (connection, handle, _) = rtnetlink::new_connection().context("opening netlink")?;
+        tokio::spawn(connection);
+        netlink_util::default_route_devs(&handle, v6).await
+    };
+    match devs.await {
+        Ok(devs) if !devs.is_empty() => Check::pass(name, format!("via {}", devs.join(", "))),
+        Ok(_)
+            if configs
+                .iter()
+                .all(|config| config.network.egress_interfaces.is_some()) =>
+        {
+            Check::pass(name, "none, but egress_interfaces is set")
+        }
+        Ok(_) => Check::warn(
+            name,
+            format!("no {family} default route; NAT and port forwards have no egress"),
+            "add a default route, or set egress_interfaces in [network]",
+        ),
+        Err(err) => Check::warn(name, format!("{err:#}"), "check that netlink is usable"),
+    }
+}
+
+async fn listen_ports(configs: &[ResolvedConfig]) -> Vec<Check> {
+    const NAME: &str = "listen port";
+    let mut checks = Vec::new();
+    for (idx, config) in configs.iter().enumerate() {
+        let port = config.server.listen_port;
+        if let Some(other) = configs[..idx]
+            .iter()
+            .find(|other| other.server.listen_port == port)
+        {
+            checks.push(Check::fail(
+                NAME,
+                format!(
+                    "{} and {} both use {port}/udp",
+                    other.interface, config.interface
+                ),
+                Some("give each interface its own listen_port".to_string()),
+            ));
+            continue;
+        }
+        if let Ok(Some(_)) = wg_iface::peer_status(&config.interface).await {
+            checks.push(Check::pass(
+                NAME,
+                format!("{port}/udp held by running {}", config.interface),
+            ));
+            continue;
+        }
+        let in_use = [
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ]
+        .into_iter()
+        .any(|addr| {
+            matches!(
+                UdpSocket::bind((addr, port)),
+                Err(err) if err.kind() == std::io::ErrorKind::AddrInUse
+            )
+        });
+        checks.push(if in_use {
+            Check::fail(
+                NAME,
+                format!("{port}/udp of {} is already in use", config.interface),
+                Some(format!(
+                    "stop what listens there (`ss -ulpn 'sport = :{port}'`) or change listen_port"
+                )),
+            )
+        } else {
+            Check::pass(NAME, format!("{port}/udp of {} is free", config.interface))
+        });
+    }
+    checks
+}
+
+async fn external_address(config: &ResolvedConfig) -> Check {
+    const NAME: &str = "external address";
+    let Some(host) = config.server.external_address.as_deref() else {
+        return Check::fail(
+            NAME,
+            format!("{} has no external_address", config.interface),
+            Some("set external_address in [server] or WG_EXTERNAL_ADDRESS".to_string()),
+        );
+    };
+    if host.parse::<IpAddr>().is_ok() {
+        return Check::pass(NAME, format!("{host} is an IP address"));
+    }
+    let port = config.server.listen_port;
+    match tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+        Ok(Ok(addrs)) => {
+            let addrs: Vec<String> = addrs.map(|addr| addr.ip().to_string()).collect();
+            Check::pass(NAME, format!("{host} resolves to {}", addrs.join(", ")))
+        }
+        Ok(Err(err)) => Check::warn(
+            NAME,
+            format!("{host} does not resolve here: {err}"),
+            "check the DNS record; peers need to resolve it to connect",
+        ),
+        Err(_) => Check::warn(
+            NAME,
+            format!("resolving {host} timed out"),
+            "check the DNS servers of this host",
+        ),
+    }
+}
+
+/// The state directory must be writable, and key files readable only by
+/// their owner.
+fn state_dir(config: &ResolvedConfig) -> Check {
+    const NAME: &str = "state dir";
+    let root = &config.paths.root;
+    if !root.exists() {
+        return Check::warn(
+            NAME,
+            format!("{} does not exist yet", root.display()),
+            "it is created on first run; mount a volume there to keep keys across restarts",
+        );
+    }
+    let probe = root.join(".rust-wg-doctor");
+    if let Err(err) = fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe)) {
+        return Check::fail(
+            NAME,
+            format!("{} is not writable: {err}", root.display()),
+            Some(format!(
+                "make {} writable by the user running rust-wg",
+                root.display()
+            )),
+        );
+    }
+    let mut exposed = Vec::new();
+    let key_files = fs::read_dir(&config.paths.peers)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| ["private.key", "preshared.key"].map(|name| entry.path().join(name)))
+        .chain([config.paths.keys.join("server.key")]);
+    for path in key_files {
+        if let Ok(meta) = fs::metadata(&path)
+            && meta.permissions().mode() & 0o077 != 0
+        {
+            exposed.push(path.display().to_string());
+        }
+    }
+    if exposed.is_empty() {
+        Check::pass(NAME, format!("{} is writable", root.display()))
+    } else {
+        Check::warn(
+            NAME,
+            format!("other users can read {}", exposed.join(", ")),
+            "chmod 600 the listed key files",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grades_sysctl_values() {
+        let check = |value, severity| {
+            sysctl_check(
+                "fwd",
+                "net.ipv4.ip_forward",
+                value,
+                severity,
+                "impact",
+                "fix",
+            )
+            .status
+        };
+        assert!(check(Some("1\n"), Status::Fail) == Status::Pass);
+        assert!(check(Some("0\n"), Status::Fail) == Status::Fail);
+        assert!(check(Some("0\n"), Status::Warn) == Status::Warn);
+        assert!(check(None, Status::Fail) == Status::Warn);
+    }
+}
@@ -0,0 +1,502 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::config::{EventsConfig, ResolvedConfig};
+use crate::wg_iface;
+
+const DEFAULT_POLL_SECS: u64 = 10;
+const DEFAULT_STALE_SECS: u64 = 180;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a hook may run before it is killed, so that one stuck hook does
+/// not hold up every later event.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events waiting for delivery; beyond this, new ones are only logged.
+const QUEUE_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EventKind {
+    Connect,
+    Disconnect,
+    /// The peer is still connected but from a different endpoint.
+    Roam,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Disconnect => "disconnect",
+            Self::Roam => "roam",
+        }
+    }
+}
+
+/// What hooks and the webhook receive.
+#[derive(Debug, Serialize)]
+struct Event {
+    event: EventKind,
+    interface: String,
+    /// `None` for peers on the device that the configuration does not know.
+    peer_id: Option<String>,
+    name: Option<String>,
+    public_key: String,
+    addresses: Vec<IpAddr>,
+    endpoint: Option<SocketAddr>,
+    previous_endpoint: Option<SocketAddr>,
+    /// Unix time of the latest handshake, if any.
+    latest_handshake: Option<u64>,
+}
+
+/// A peer's state as of one poll.
+#[derive(Debug, Clone, PartialEq)]
+struct Observed {
+    connected: bool,
+    endpoint: Option<SocketAddr>,
+    latest_handshake: Option<u64>,
+}
+
+/// A configured peer, looked up by interface and public key.
+struct Known {
+    id: String,
+    name: Option<String>,
+    addresses: Vec<IpAddr>,
+}
+
+#[derive(Default)]
+struct Applied {
+    interfaces: Vec<String>,
+    peers: HashMap<(String, String), Known>,
+}
+
+/// Handle to the watcher task, updated after every apply and reload.
+pub struct Events {
+    applied: Arc<Mutex<Applied>>,
+}
+
+impl Events {
+    /// Starts polling the configured interfaces in the background, with a
+    /// single task delivering the events in the order they happened.
+    pub fn start(cfg: EventsConfig) -> Result<Self> {
+        let webhook = cfg.webhook.as_deref().map(Webhook::parse).transpose()?;
+        let notify = Notify {
+            hooks: cfg.hooks,
+            hook_timeout: HOOK_TIMEOUT,
+            webhook,
+        };
+        let poll = Duration::from_secs(cfg.poll_interval_secs.unwrap_or(DEFAULT_POLL_SECS).max(1));
+        let stale = Duration::from_secs(cfg.stale_after_secs.unwrap_or(DEFAULT_STALE_SECS));
+        let applied = Arc::new(Mutex::new(Applied::default()));
+        let (queue, pending) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(notify.deliver(pending));
+        tokio::spawn(watch(poll, stale, applied.clone(), queue));
+        Ok(Self { applied })
+    }
+
+    pub fn update(&self, configs: &[ResolvedConfig]) {
+        let mut applied = self.applied.lock().unwrap_or_else(|err| err.into_inner());
+        applied.interfaces = configs.iter().map(|c| c.interface.clone()).collect();
+        applied.peers = configs
+            .iter()
+            .flat_map(|config| {
+                config.leases.iter().map(|lease| {
+                    let name = config
+                        .peers
+                        .iter()
+                        .find(|peer| peer.id == lease.peer_id)
+                        .and_then(|peer| peer.name.clone());
+                    let addresses = std::iter::once(IpAddr::V4(lease.v4))
+                        .chain(lease.v6.map(IpAddr::V6))
+                        .collect();
+                    let known = Known {
+                        id: lease.peer_id.clone(),
+                        name,
+                        addresses,
+                    };
+                    ((config.interface.clone(), lease.public_key.clone()), known)
+                })
+            })
+            .collect();
+    }
+}
+
+async fn watch(
+    poll: Duration,
+    stale: Duration,
+    applied: Arc<Mutex<Applied>>,
+    queue: mpsc::Sender<Event>,
+) {
+    let mut previous: HashMap<(String, String), Observed> = HashMap::new();
+    // Interfaces the previous poll read. The first poll of an interface only
+    // seeds `previous`, so peers connected at startup raise no events.
+    let mut polled: Vec<String> = Vec::new();
+    let mut interval = tokio::time::interval(poll);
+    loop {
+        interval.tick().await;
+        let interfaces = applied
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .interfaces
+            .clone();
+        let Some(current) = observe(&interfaces, stale).await else {
+            continue;
+        };
+
+        let mut keys: Vec<_> = previous
+            .keys()
+            .chain(current.keys())
+            .filter(|(interface, _)| polled.contains(interface))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let (before, after) = (previous.get(&key), current.get(&key));
+            let Some(kind) = transition(before, after) else {
+                continue;
+            };
+            let event = {
+                let applied = applied.lock().unwrap_or_else(|err| err.into_inner());
+                let known = applied.peers.get(&key);
+                let latest = after.or(before);
+                Event {
+                    event: kind,
+                    interface: key.0.clone(),
+                    peer_id: known.map(|known| known.id.clone()),
+                    name: known.and_then(|known| known.name.clone()),
+                    public_key: key.1.clone(),
+                    addresses: known
+                        .map(|known| known.addresses.clone())
+                        .unwrap_or_default(),
+                    endpoint: latest.and_then(|observed| observed.endpoint),
+                    previous_endpoint: before.and_then(|observed| observed.endpoint),
+                    latest_handshake: latest.and_then(|observed| observed.latest_handshake),
+                }
+            };
+            log(&event);
+            if queue.try_send(event).is_err() {
+                eprintln!("events: delivery queue is full; skipping hooks and webhook");
+            }
+        }
+        previous = current;
+        polled = interfaces;
+    }
+}
+
+/// Reads every interface's peers; `None` if any read fails, so a transient
+/// error is not mistaken for every peer disconnecting.
+async fn observe(
+    interfaces: &[String],
+    stale: Duration,
+) -> Option<HashMap<(String, String), Observed>> {
+    let now = SystemTime::now();
+    let mut out = HashMap::new();
+    for interface in interfaces {
+        let peers = match wg_iface::peer_status(interface).await {
+            Ok(peers) => peers.unwrap_or_default(),
+            Err(err) => {
+                eprintln!("events: reading peers of {interface} failed: {err:#}");
+                return None;
+            }
+        };
+        for peer in peers {
+            let connected = peer
+                .last_handshake
+                .is_some_and(|at| now.duration_since(at).unwrap_or_default() < stale);
+            let observed = Observed {
+                connected,
+                endpoint: peer.endpoint,
+                latest_handshake: peer
+                    .last_handshake
+                    .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs()),
+            };
+            out.insert((interface.clone(), peer.public_key), observed);
+        }
+    }
+    Some(out)
+}
+
+/// The event between two polls of one peer; a peer missing from a poll
+/// counts as disconnected.
+fn transition(before: Option<&Observed>, after: Option<&Observed>) -> Option<EventKind> {
+    let was = before.is_some_and(|observed| observed.connected);
+    let is = after.is_some_and(|observed| observed.connected);
+    match (was, is) {
+        (false, true) => Some(EventKind::Connect),
+        (true, false) => Some(EventKind::Disconnect),
+        (true, true) if before?.endpoint != after?.endpoint => Some(EventKind::Roam),
+        _ => None,
+    }
+}
+
+fn log(event: &Event) {
+    let peer = event
+        .name
+        .as_deref()
+        .or(event.peer_id.as_deref())
+        .unwrap_or(&event.public_key);
+    let endpoint = |addr: Option<SocketAddr>| addr.map_or("unknown".to_string(), |a| a.to_string());
+    let interface = &event.interface;
+    match event.event {
+        EventKind::Connect => eprintln!(
+            "events: {peer} connected to {interface} from {}",
+            endpoint(event.endpoint)
+        ),
+        EventKind::Disconnect => eprintln!("events: {peer} disconnected from {interface}"),
+        EventKind::Roam => eprintln!(
+            "events: {peer} roamed on {interface} from {} to {}",
+            endpoint(event.previous_endpoint),
+            endpoint(event.endpoint)
+        ),
+    }
+}
+
+struct Notify {
+    hooks: Vec<Vec<String>>,
+    hook_timeout: Duration,
+    webhook: Option<Webhook>,
+}
+
+impl Notify {
+    /// Sends each queued event in turn until the watcher stops.
+    async fn deliver(self, mut pending: mpsc::Receiver<Event>) {
+        while let Some(event) = pending.recv().await {
+            self.send(event).await;
+        }
+    }
+
+    async fn send(&self, event: Event) {
+        for hook in &self.hooks {
+            match tokio::time::timeout(self.hook_timeout, run_hook(hook, &event)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => eprintln!("events: hook {hook:?} failed: {err:#}"),
+                Err(_) => eprintln!("events: hook {hook:?} timed out"),
+            }
+        }
+        if let Some(webhook) = &self.webhook {
+            let post = tokio::time::timeout(WEBHOOK_TIMEOUT, webhook.post(&event));
+            match post.await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => eprintln!("events: webhook {} failed: {err:#}", webhook.url),
+                Err(_) => eprintln!("events: webhook {} timed out", webhook.url),
+            }
+        }
+    }
+}
+
+async fn run_hook(hook: &[String], event: &Event) -> Result<()> {
+    let (program, args) = hook.split_first().context("empty hook command")?;
+    let addresses: Vec<String> = event.addresses.iter().map(IpAddr::to_string).collect();
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .env("WG_EVENT", event.event.name())
+        .env("WG_INTERFACE", &event.interface)
+        .env("WG_PEER_ID", event.peer_id.as_deref().unwrap_or_default())
+        .env("WG_PEER_NAME", event.name.as_deref().unwrap_or_default())
+        .env("WG_PEER_PUBLIC_KEY", &event.public_key)
+        .env("WG_PEER_ADDRESSES", addresses.join(","))
+        .env("WG_PEER_ENDPOINT", display_opt(event.endpoint))
+        .env(
+            "WG_PEER_PREVIOUS_ENDPOINT",
+            display_opt(event.previous_endpoint),
+        )
+        .stdin(std::process::Stdio::null())
+        // Killed when the timeout drops the future.
+        .kill_on_drop(true)
+        .status()
+        .await
+        .with_context(|| format!("starting {program}"))?;
+    if !status.success() {
+        anyhow::bail!("{program} exited with {status}");
+    }
+    Ok(())
+}
+
+fn display_opt(addr: Option<SocketAddr>) -> String {
+    addr.map(|addr| addr.to_string()).unwrap_or_default()
+}
+
+/// A plain-HTTP endpoint on this host, typically a local relay.
+#[derive(Debug)]
+struct Webhook {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Webhook {
+    fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("http://").with_context(|| {
+            format!("webhook {url}: only http:// URLs are supported; use a local relay for https")
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse()
+                    .with_context(|| format!("webhook {url}: invalid port {port}"))?;
+                (host, port)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            anyhow::bail!("webhook {url}: missing host");
+        }
+        // Events go out unencrypted and name peers and their endpoints.
+        let loopback = host.eq_ignore_ascii_case("localhost")
+            || host.parse::<IpAddr>().is_ok_and(|addr| addr.is_loopback());
+        if !loopback {
+            anyhow::bail!(
+                "webhook {url}: {host} is not a loopback address; plain HTTP may only \
+                 reach a relay on this host"
+            );
+        }
+        Ok(Self {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// The `Host` header value, with IPv6 addresses in brackets.
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    async fn post(&self, event: &Event) -> Result<()> {
+        let body = serde_json::to_string(event).context("serializing event")?;
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .context("connecting")?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.authority(),
+            body.len()
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .context("sending")?;
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .await
+            .context("reading response")?;
+        let status_line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+        let status = String::from_utf8_lossy(status_line);
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => anyhow::bail!("unexpected response {status:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_transitions() {
+        let at = |connected, port| Observed {
+            connected,
+            endpoint: Some(SocketAddr::from(([192, 0, 2, 1], port))),
+            latest_handshake: Some(1),
+        };
+        assert_eq!(
+            transition(None, Some(&at(true, 1))),
+            Some(EventKind::Connect)
+        );
+        assert_eq!(transition(None, Some(&at(false, 1))), None);
+        assert_eq!(
+            transition(Some(&at(true, 1)), Some(&at(false, 1))),
+            Some(EventKind::Disconnect)
+        );
+        assert_eq!(
+            transition(Some(&at(true, 1)), None),
+            Some(EventKind::Disconnect)
+        );
+        assert_eq!(
+            transition(Some(&at(true, 1)), Some(&at(true, 2))),
+            Some(EventKind::Roam)
+        );
+        assert_eq!(transition(Some(&at(true, 1)), Some(&at(true, 1))), None);
+    }
+
+    #[tokio::test]
+    async fn times_out_hooks_without_holding_up_later_events() {
+        let log = std::env::temp_dir().join(format!("rust-wg-hooks-{}", std::process::id()));
+        let _ = std::fs::remove_file(&log);
+        let notify = Notify {
+            hooks: vec![
+                vec!["sleep".to_string(), "30".to_string()],
+                vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    format!("echo \"$WG_EVENT\" >> {}", log.display()),
+                ],
+            ],
+            hook_timeout: Duration::from_millis(200),
+            webhook: None,
+        };
+        let event = |kind| Event {
+            event: kind,
+            interface: "wg0".to_string(),
+            peer_id: Some("peer-laptop".to_string()),
+            name: Some("laptop".to_string()),
+            public_key: String::new(),
+            addresses: Vec::new(),
+            endpoint: None,
+            previous_endpoint: None,
+            latest_handshake: None,
+        };
+        let (queue, pending) = mpsc::channel(QUEUE_LEN);
+        queue.try_send(event(EventKind::Connect)).unwrap();
+        queue.try_send(event(EventKind::Disconnect)).unwrap();
+        drop(queue);
+
+        let started = std::time::Instant::now();
+        notify.deliver(pending).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let delivered = std::fs::read_to_string(&log).unwrap();
+        let _ = std::fs::remove_file(&log);
+        assert_eq!(delivered, "connect\ndisconnect\n");
+    }
+
+    #[test]
+    fn parses_webhook_urls() {
+        let hook = Webhook::parse("http://[::1]:8080/wg/events").unwrap();
+        assert_eq!((hook.host.as_str(), hook.port), ("::1", 8080));
+        assert_eq!(hook.path, "/wg/events");
+        assert_eq!(hook.authority(), "[::1]:8080");
+        let hook = Webhook::parse("http://localhost").unwrap();
+        assert_eq!(
+            (hook.host.as_str(), hook.port, hook.path.as_str()),
+            ("localhost", 80, "/")
+        );
+        assert_eq!(hook.authority(), "localhost:80");
+        assert!(Webhook::parse("http://127.0.0.2:9000/").is_ok());
+        assert!(Webhook::parse("https://example.com/hook").is_err());
+        assert!(Webhook::parse("http://relay.example.com/hook").is_err());
+        assert!(Webhook::parse("http://192.0.2.1/hook").is_err());
+    }
+}
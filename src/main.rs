@@ -1,10 +1,16 @@
 mod cli;
 mod config;
+mod doctor;
+mod events;
+mod metrics;
 mod module_check;
 mod netlink_util;
 mod nft;
+mod plan;
 mod runtime;
+mod show_config;
 mod shutdown;
+mod status;
 mod wg_iface;
 
 use clap::Parser;
@@ -12,13 +18,44 @@ use clap::Parser;
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = cli::Cli::parse();
+    let locations = config::Locations {
+        config: cli.config,
+        state_dir: cli.state_dir,
+    };
 
     match cli.command {
-        cli::Commands::Run => runtime::run().await,
-        cli::Commands::ShowPeer { peers } => {
-            runtime::show_peer(peers);
-            Ok(())
-        }
-        cli::Commands::Generate => runtime::generate(),
+        cli::Commands::Run {
+            dry_run: true,
+            json,
+        } => plan::run(&locations, json).await,
+        cli::Commands::Run { .. } => runtime::run(&locations).await,
+        cli::Commands::ShowPeer {
+            peers,
+            interface,
+            conf_only,
+            qr_only,
+        } => runtime::show_peer(&locations, &peers, interface.as_deref(), conf_only, qr_only),
+        cli::Commands::Generate {
+            dry_run: true,
+            json,
+        } => plan::generate(&locations, json).await,
+        cli::Commands::Generate { .. } => runtime::generate(&locations),
+        cli::Commands::Status { json } => status::show(&locations, json).await,
+        cli::Commands::Cleanup => runtime::cleanup(&locations).await,
+        cli::Commands::Doctor => doctor::run(&locations).await,
+        cli::Commands::Peer {
+            command:
+                cli::PeerCommands::Revoke {
+                    peer,
+                    interface,
+                    delete,
+                },
+        } => runtime::revoke_peer(&locations, &peer, interface.as_deref(), delete).await,
+        cli::Commands::Config {
+            command: cli::ConfigCommands::Check,
+        } => runtime::check_config(&locations),
+        cli::Commands::Config {
+            command: cli::ConfigCommands::Show { json },
+        } => show_config::run(&locations, json),
     }
 }
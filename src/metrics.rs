@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{self, PeerRecord, ResolvedConfig};
+use crate::{nft, wg_iface};
+
+/// Largest request head read before answering; scrapers send far less.
+const MAX_REQUEST: usize = 8192;
+/// How long a client may take to send its request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the run loop last applied; live peer state is read per scrape.
+#[derive(Default)]
+struct Applied {
+    /// Each interface with the peers of its applied configuration.
+    interfaces: Vec<(String, Vec<PeerRecord>)>,
+    rules: nft::AppliedRules,
+}
+
+/// Handle to the metrics endpoint, updated after every apply and reload.
+pub struct Metrics {
+    applied: Arc<Mutex<Applied>>,
+}
+
+impl Metrics {
+    /// Binds `listen` and serves `GET /metrics` in the background.
+    pub async fn serve(listen: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("binding metrics listener on {listen}"))?;
+        eprintln!("metrics: serving on http://{listen}/metrics");
+        let applied = Arc::new(Mutex::new(Applied::default()));
+        tokio::spawn(accept_loop(listener, applied.clone()));
+        Ok(Self { applied })
+    }
+
+    pub fn update(&self, configs: &[ResolvedConfig], nft_handles: &nft::NftHandles) {
+        let mut applied = self.applied.lock().unwrap_or_else(|err| err.into_inner());
+        applied.interfaces = configs
+            .iter()
+            .map(|config| (config.interface.clone(), config::peer_records(config)))
+            .collect();
+        applied.rules = nft_handles.rules();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, applied: Arc<Mutex<Applied>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(stream, applied.clone()));
+            }
+            Err(err) => eprintln!("metrics: accepting connection failed: {err}"),
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, applied: Arc<Mutex<Applied>>) {
+    let Ok(Some(request)) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await
+    else {
+        return;
+    };
+    let line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let (status, body) = match line {
+        b"GET /metrics HTTP/1.0" | b"GET /metrics HTTP/1.1" => match render(&applied).await {
+            Ok(body) => ("200 OK", body),
+            Err(err) => {
+                eprintln!("metrics: collecting failed: {err:#}");
+                ("500 Internal Server Error", format!("{err:#}\n"))
+            }
+        },
+        _ => ("404 Not Found", "try /metrics\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/// Reads up to the end of the request head; `None` if the client hangs up.
+async fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(request)
+}
+
+/// Renders the Prometheus text exposition format.
+async fn render(applied: &Mutex<Applied>) -> Result<String> {
+    let (interfaces, rules) = {
+        let applied = applied.lock().unwrap_or_else(|err| err.into_inner());
+        (applied.interfaces.clone(), applied.rules.clone())
+    };
+    let now = SystemTime::now();
+
+    let mut up = Vec::new();
+    let mut rx = Vec::new();
+    let mut tx = Vec::new();
+    let mut age = Vec::new();
+    for (interface, records) in &interfaces {
+        let interface = interface.as_str();
+        let Some(peers) = wg_iface::peer_status(interface).await? else {
+            up.push((labels(&[("interface", interface)]), 0.0));
+            continue;
+        };
+        up.push((labels(&[("interface", interface)]), 1.0));
+        for peer in peers {
+            let record = config::find_record(records, &peer.public_key);
+            let id = record.map_or(peer.public_key.as_str(), |record| record.id.as_str());
+            let name = record
+                .and_then(|record| record.name.as_deref())
+                .unwrap_or(id);
+            let peer_labels = labels(&[("interface", interface), ("peer", id), ("name", name)]);
+            rx.push((peer_labels.clone(), peer.rx_bytes as f64));
+            tx.push((peer_labels.clone(), peer.tx_bytes as f64));
+            let seconds = peer.last_handshake.map_or(f64::INFINITY, |at| {
+                now.duration_since(at).unwrap_or_default().as_secs_f64()
+            });
+            age.push((peer_labels, seconds));
+        }
+    }
+    let configured: Vec<_> = interfaces
+        .iter()
+        .map(|(interface, records)| (labels(&[("interface", interface)]), records.len() as f64))
+        .collect();
+    // Reading the counters blocks on netlink.
+    let counters = tokio::task::spawn_blocking(move || nft::rule_counters(&rules))
+        .await
+        .context("reading nftables counters")??;
+    let rule_labels = |counter: &nft::RuleCounter| {
+        labels(&[
+            ("family", counter.family),
+            ("table", &counter.table),
+            ("chain", &counter.chain),
+            ("rule", &counter.rule),
+        ])
+    };
+    let packets: Vec<_> = counters
+        .iter()
+        .map(|counter| (rule_labels(counter), counter.packets as f64))
+        .collect();
+    let bytes: Vec<_> = counters
+        .iter()
+        .map(|counter| (rule_labels(counter), counter.bytes as f64))
+        .collect();
+
+    let mut out = String::new();
+    let families = [
+        (
+            "wg_interface_up",
+            "gauge",
+            "Whether the WireGuard interface exists.",
+            &up,
+        ),
+        (
+            "wg_configured_peers",
+            "gauge",
+            "Peers in the applied configuration.",
+            &configured,
+        ),
+        (
+            "wg_peer_receive_bytes_total",
+            "counter",
+            "Bytes received from the peer.",
+            &rx,
+        ),
+        (
+            "wg_peer_transmit_bytes_total",
+            "counter",
+            "Bytes sent to the peer.",
+            &tx,
+        ),
+        (
+            "wg_peer_last_handshake_seconds",
+            "gauge",
+            "Seconds since the latest handshake; +Inf if none.",
+            &age,
+        ),
+        (
+            "wg_nft_rule_packets_total",
+            "counter",
+            "Packets that reached the rule's action.",
+            &packets,
+        ),
+        (
+            "wg_nft_rule_bytes_total",
+            "counter",
+            "Bytes that reached the rule's action.",
+            &bytes,
+        ),
+    ];
+    for (name, kind, help, samples) in families {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (labels, value) in samples.iter() {
+            let _ = writeln!(out, "{name}{{{labels}}} {}", format_value(*value));
+        }
+    }
+    Ok(out)
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(
+            labels(&[("rule", "iifname \"wg0\" accept"), ("name", "a\\b")]),
+            r#"rule="iifname \"wg0\" accept",name="a\\b""#
+        );
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(1024.0), "1024");
+    }
+}
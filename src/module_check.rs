@@ -5,7 +5,27 @@ use crate::netlink_util::{get_link_by_name, netlink_err_code};
 
 const CHECK_IFACE: &str = "wg-check";
 
+/// Why WireGuard interfaces cannot be created here.
+pub enum Missing {
+    Privileges,
+    Module,
+}
+
 pub async fn ensure_wireguard_support() -> Result<()> {
+    match probe().await? {
+        None => Ok(()),
+        Some(Missing::Privileges) => anyhow::bail!(
+            "wireguard interface creation failed: missing CAP_NET_ADMIN or insufficient privileges"
+        ),
+        Some(Missing::Module) => {
+            anyhow::bail!("wireguard interface creation failed: kernel module not available")
+        }
+    }
+}
+
+/// Creates and deletes a throwaway WireGuard link; returns what is missing
+/// if the kernel refuses.
+pub async fn probe() -> Result<Option<Missing>> {
     let (connection, handle, _) = rtnetlink::new_connection().context("opening netlink")?;
     tokio::spawn(connection);
 
@@ -22,12 +42,10 @@ pub async fn ensure_wireguard_support() -> Result<()> {
     if let Err(err) = add {
         if let Some(code) = netlink_err_code(&err) {
             if code == -libc::EPERM {
-                anyhow::bail!(
-                    "wireguard interface creation failed: missing CAP_NET_ADMIN or insufficient privileges"
-                );
+                return Ok(Some(Missing::Privileges));
             }
             if code == -libc::EOPNOTSUPP || code == -libc::ENODEV {
-                anyhow::bail!("wireguard interface creation failed: kernel module not available");
+                return Ok(Some(Missing::Module));
             }
         }
         return Err(err).context("creating wireguard interface for module check");
@@ -37,5 +55,5 @@ pub async fn ensure_wireguard_support() -> Result<()> {
         let _ = handle.link().del(link.header.index).execute().await;
     }
 
-    Ok(())
+    Ok(None)
 }
@@ -1,7 +1,11 @@
 use anyhow::{Context, Result};
 use futures::TryStreamExt;
-use netlink_packet_route::link::LinkMessage;
-use rtnetlink::Handle;
+use netlink_packet_route::link::{LinkAttribute, LinkMessage};
+use netlink_packet_route::route::{RouteAttribute, RouteMessage};
+use rtnetlink::{Handle, RouteMessageBuilder};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const RT_TABLE_MAIN: u32 = 254;
 
 pub async fn get_link_by_name(handle: &Handle, name: &str) -> Result<Option<LinkMessage>> {
     match handle
@@ -30,3 +34,65 @@ pub fn netlink_err_code(err: &rtnetlink::Error) -> Option<i32> {
         _ => None,
     }
 }
+
+/// Interfaces of the main table's default routes (including every next hop
+/// of a multipath route), in kernel order without duplicates.
+pub async fn default_route_devs(handle: &Handle, v6: bool) -> Result<Vec<String>> {
+    let request = if v6 {
+        RouteMessageBuilder::<Ipv6Addr>::new().build()
+    } else {
+        RouteMessageBuilder::<Ipv4Addr>::new().build()
+    };
+    let mut routes = handle.route().get(request).execute();
+    let mut indexes = Vec::new();
+    while let Some(route) = routes.try_next().await.context("dumping routes")? {
+        if route.header.destination_prefix_length != 0 || route_table(&route) != RT_TABLE_MAIN {
+            continue;
+        }
+        for attr in &route.attributes {
+            match attr {
+                RouteAttribute::Oif(index) => indexes.push(*index),
+                RouteAttribute::MultiPath(hops) => {
+                    indexes.extend(hops.iter().map(|hop| hop.interface_index));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut devs = Vec::new();
+    for index in indexes {
+        let link = handle
+            .link()
+            .get()
+            .match_index(index)
+            .execute()
+            .try_next()
+            .await
+            .with_context(|| format!("getting link {index}"))?;
+        let name = link.and_then(|link| {
+            link.attributes.into_iter().find_map(|attr| match attr {
+                LinkAttribute::IfName(name) => Some(name),
+                _ => None,
+            })
+        });
+        if let Some(name) = name
+            && !devs.contains(&name)
+        {
+            devs.push(name);
+        }
+    }
+    Ok(devs)
+}
+
+/// The full table ID, which only fits the header for tables below 256.
+fn route_table(route: &RouteMessage) -> u32 {
+    route
+        .attributes
+        .iter()
+        .find_map(|attr| match attr {
+            RouteAttribute::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(u32::from(route.header.table))
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::config::{AclConfig, AclEntry, AclRules, Policy, Protocol, ResolvedConfig};
+use crate::nft::ruleset::{Chain, Family, L4Proto, Rule, Stmt};
+
+pub(super) const CHAIN_ACL: &str = "acl";
+
+/// A peer's ACL after merging its own rules with those of its groups.
+struct PeerAcl<'a> {
+    default: Policy,
+    allow: Vec<&'a AclEntry>,
+    deny: Vec<&'a AclEntry>,
+}
+
+/// Builds the regular `acl` chain for `family`, keyed by each restricted
+/// peer's source addresses, or `None` if no peer is restricted. Traffic that
+/// falls through returns to the forward chain.
+pub(super) fn acl_chain(config: &ResolvedConfig, family: Family) -> Result<Option<Chain>> {
+    let mut rules = Vec::new();
+    for peer in &config.peers {
+        let acl = resolve(
+            &peer.id,
+            peer.settings.acl.as_ref(),
+            peer.settings.groups.as_deref().unwrap_or_default(),
+            &config.acl,
+        )?;
+        if acl.default == Policy::Allow && acl.deny.is_empty() {
+            continue;
+        }
+        let mut sources = config
+            .lease(&peer.id)
+            .map(|lease| lease.addresses())
+            .unwrap_or_default();
+        sources.extend(peer.routed_prefixes()?);
+        for source in sources.into_iter().filter(|net| family_of(net) == family) {
+            for entry in &acl.deny {
+                rules.extend(entry_rules(&peer.id, source, entry, Stmt::Drop, family)?);
+            }
+            if acl.default == Policy::Deny {
+                for entry in &acl.allow {
+                    rules.extend(entry_rules(&peer.id, source, entry, Stmt::Accept, family)?);
+                }
+                rules.push(Rule(vec![Stmt::Saddr(source), Stmt::Drop]));
+            }
+        }
+    }
+    if rules.is_empty() {
+        return Ok(None);
+    }
+    rules.insert(0, Rule(vec![Stmt::CtEstablished, Stmt::Accept]));
+    Ok(Some(Chain {
+        name: CHAIN_ACL.to_string(),
+        base: None,
+        rules,
+    }))
+}
+
+/// The peer's own `default` wins; otherwise `deny` from any group wins over
+/// `allow`, and `[acl] default` applies if neither sets one.
+fn resolve<'a>(
+    peer_id: &str,
+    own: Option<&'a AclRules>,
+    groups: &[String],
+    acl: &'a AclConfig,
+) -> Result<PeerAcl<'a>> {
+    let mut sets = Vec::from_iter(own);
+    for group in groups {
+        sets.push(
+            acl.groups
+                .get(group)
+                .with_context(|| format!("peer {peer_id} is in unknown acl group {group}"))?,
+        );
+    }
+    let group_default = sets
+        .iter()
+        .filter_map(|set| set.default)
+        .max_by_key(|policy| *policy == Policy::Deny);
+    Ok(PeerAcl {
+        default: own
+            .and_then(|rules| rules.default)
+            .or(group_default)
+            .unwrap_or(acl.default),
+        allow: sets.iter().flat_map(|set| &set.allow).collect(),
+        deny: sets.iter().flat_map(|set| &set.deny).collect(),
+    })
+}
+
+fn entry_rules(
+    peer_id: &str,
+    source: IpNet,
+    entry: &AclEntry,
+    verdict: Stmt,
+    family: Family,
+) -> Result<Vec<Rule>> {
+    let mut base = vec![Stmt::Saddr(source)];
+    if let Some(dest) = entry.dest.as_deref() {
+        let dest = dest
+            .parse::<IpNet>()
+            .or_else(|_| dest.parse::<IpAddr>().map(IpNet::from))
+            .with_context(|| format!("parsing acl dest {dest} for {peer_id}"))?;
+        if family_of(&dest) != family {
+            return Ok(Vec::new());
+        }
+        base.push(Stmt::Daddr(dest.trunc()));
+    }
+    let protos = match entry.proto {
+        Some(proto) => vec![l4proto(proto)],
+        None if entry.ports.is_empty() => Vec::new(),
+        None => vec![L4Proto::Tcp, L4Proto::Udp],
+    };
+
+    let with = |extra: Stmt| {
+        let mut stmts = base.clone();
+        stmts.push(extra);
+        stmts.push(verdict.clone());
+        Rule(stmts)
+    };
+    if protos.is_empty() {
+        let mut stmts = base.clone();
+        stmts.push(verdict.clone());
+        return Ok(vec![Rule(stmts)]);
+    }
+    let mut rules = Vec::new();
+    for proto in protos {
+        if entry.ports.is_empty() {
+            rules.push(with(Stmt::L4Proto(proto)));
+        }
+        for port in &entry.ports {
+            rules.push(with(Stmt::Dport(proto, *port)));
+        }
+    }
+    Ok(rules)
+}
+
+pub(super) fn l4proto(proto: Protocol) -> L4Proto {
+    match proto {
+        Protocol::Tcp => L4Proto::Tcp,
+        Protocol::Udp => L4Proto::Udp,
+    }
+}
+
+pub(super) fn family_of(net: &IpNet) -> Family {
+    match net {
+        IpNet::V4(_) => Family::Ip,
+        IpNet::V6(_) => Family::Ip6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_entries_per_protocol_and_port() {
+        let entry = AclEntry {
+            dest: Some("10.3.0.20".to_string()),
+            proto: None,
+            ports: vec![443],
+        };
+        let rules = entry_rules(
+            "peer-contractor",
+            "10.66.0.9/32".parse().unwrap(),
+            &entry,
+            Stmt::Accept,
+            Family::Ip,
+        )
+        .unwrap();
+        let rendered: Vec<String> = rules.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            [
+                "ip saddr 10.66.0.9/32 ip daddr 10.3.0.20/32 tcp dport 443 accept",
+                "ip saddr 10.66.0.9/32 ip daddr 10.3.0.20/32 udp dport 443 accept",
+            ]
+        );
+
+        let v6_only = entry_rules(
+            "peer-contractor",
+            "fd66::9/128".parse().unwrap(),
+            &entry,
+            Stmt::Accept,
+            Family::Ip6,
+        )
+        .unwrap();
+        assert!(v6_only.is_empty());
+    }
+
+    #[test]
+    fn peer_default_overrides_groups() {
+        let mut acl = AclConfig::default();
+        acl.groups.insert(
+            "contractors".to_string(),
+            AclRules {
+                default: Some(Policy::Deny),
+                ..AclRules::default()
+            },
+        );
+        let groups = ["contractors".to_string()];
+        assert_eq!(
+            resolve("p", None, &groups, &acl).unwrap().default,
+            Policy::Deny
+        );
+
+        let own = AclRules {
+            default: Some(Policy::Allow),
+            ..AclRules::default()
+        };
+        assert_eq!(
+            resolve("p", Some(&own), &groups, &acl).unwrap().default,
+            Policy::Allow
+        );
+        assert!(resolve("p", None, &["missing".to_string()], &acl).is_err());
+    }
+}
@@ -0,0 +1,810 @@
+use anyhow::{Context, Result};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use rtnetlink::Handle;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::config::{NatMode, NftTable, Policy, ResolvedConfig};
+use crate::netlink_util;
+
+mod acl;
+mod netlink;
+mod port_forward;
+mod ruleset;
+
+use netlink::NftSocket;
+use port_forward::PortForwardRules;
+use ruleset::{BaseChain, Chain, ChainType, Family, Hook, Rule, Stmt, Table};
+
+const TABLE_V4: &str = "wg_nat_v4";
+const TABLE_V6: &str = "wg_nat_v6";
+const TABLE_FILTER_V4: &str = "wg_filter_v4";
+const TABLE_FILTER_V6: &str = "wg_filter_v6";
+const CHAIN_PRE: &str = "prerouting";
+const CHAIN: &str = "postrouting";
+const CHAIN_FWD: &str = "forward";
+
+pub struct NftHandles {
+    tables: Vec<(Family, String, u64)>,
+    rules: AppliedRules,
+}
+
+impl NftHandles {
+    /// The applied tables, for reading their rule counters later.
+    pub fn rules(&self) -> AppliedRules {
+        self.rules.clone()
+    }
+}
+
+/// The tables as last applied; cheap to clone.
+#[derive(Clone, Default)]
+pub struct AppliedRules(Arc<Vec<Table>>);
+
+/// Packets and bytes that reached the action of one applied rule.
+pub struct RuleCounter {
+    pub family: &'static str,
+    pub table: String,
+    pub chain: String,
+    /// The rule in `nft` syntax.
+    pub rule: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Reads the counters of every rule in `rules`; tables that no longer exist
+/// are skipped.
+pub fn rule_counters(rules: &AppliedRules) -> Result<Vec<RuleCounter>> {
+    let mut out = Vec::new();
+    if rules.0.is_empty() {
+        return Ok(out);
+    }
+    let mut socket = NftSocket::open().context("opening nftables netlink socket")?;
+    for table in rules.0.iter() {
+        let counts = match socket.rule_counters(table.family, &table.name) {
+            Ok(counts) => counts,
+            Err(err) if err.is_not_found() => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("reading counters of table {}", table.name));
+            }
+        };
+        for chain in &table.chains {
+            let chain_counts = counts.iter().filter(|(name, ..)| *name == chain.name);
+            for (rule, (_, packets, bytes)) in chain.rules.iter().zip(chain_counts) {
+                out.push(RuleCounter {
+                    family: table.family.name(),
+                    table: table.name.clone(),
+                    chain: chain.name.clone(),
+                    rule: rule.to_string(),
+                    packets: *packets,
+                    bytes: *bytes,
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// A table `apply` would load, in `nft` syntax.
+#[derive(Serialize)]
+pub struct TablePlan {
+    pub family: &'static str,
+    pub name: String,
+    pub script: String,
+}
+
+/// The tables `apply` would load for `configs`, without touching nftables.
+pub async fn plan(configs: &[ResolvedConfig]) -> Result<Vec<TablePlan>> {
+    Ok(build_tables(configs)
+        .await?
+        .iter()
+        .map(|table| TablePlan {
+            family: table.family.name(),
+            name: table.name.clone(),
+            script: table.to_string(),
+        })
+        .collect())
+}
+
+/// Tables built for a set of configurations, ready to be loaded.
+pub struct BuiltTables(Vec<Table>);
+
+/// Builds the tables of every interface in `configs` without loading them,
+/// so that a reload can fail before it has changed anything.
+pub async fn build(configs: &[ResolvedConfig]) -> Result<BuiltTables> {
+    Ok(BuiltTables(build_tables(configs).await?))
+}
+
+/// Applies the tables of every interface in `configs` in one batch.
+pub async fn apply(configs: &[ResolvedConfig]) -> Result<NftHandles> {
+    load(build_tables(configs).await?)
+}
+
+fn load(tables: Vec<Table>) -> Result<NftHandles> {
+    if tables.is_empty() {
+        return Ok(NftHandles {
+            tables: Vec::new(),
+            rules: AppliedRules::default(),
+        });
+    }
+
+    let mut socket = NftSocket::open().context("opening nftables netlink socket")?;
+    socket
+        .replace_tables(&tables)
+        .context("applying nftables nat and forward rules")?;
+
+    let mut handles = Vec::new();
+    for table in &tables {
+        let handle = socket
+            .table_handle(table.family, &table.name)
+            .with_context(|| format!("looking up handle of table {}", table.name))?;
+        handles.push((table.family, table.name.clone(), handle));
+    }
+    Ok(NftHandles {
+        tables: handles,
+        rules: AppliedRules(Arc::new(tables)),
+    })
+}
+
+/// Loads `tables`, replacing the applied ones in place, and drops tables
+/// that the new configuration no longer needs.
+pub fn reload(handles: &mut NftHandles, tables: BuiltTables) -> Result<()> {
+    let updated = load(tables.0)?;
+    let stale = NftHandles {
+        tables: handles
+            .tables
+            .drain(..)
+            .filter(|(family, name, _)| {
+                !updated
+                    .tables
+                    .iter()
+                    .any(|(f, n, _)| f == family && n == name)
+            })
+            .collect(),
+        rules: AppliedRules::default(),
+    };
+    *handles = updated;
+    teardown(&stale)
+}
+
+pub fn teardown(handles: &NftHandles) -> Result<()> {
+    if handles.tables.is_empty() {
+        return Ok(());
+    }
+    let mut socket = NftSocket::open().context("opening nftables netlink socket")?;
+    for (family, name, handle) in &handles.tables {
+        match socket.delete_table(*family, *handle) {
+            Ok(()) => {}
+            Err(err) if err.is_not_found() => {}
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("deleting table {} {name}", family.name()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that nf_tables answers over netlink by looking up a table that
+/// does not exist.
+pub fn probe() -> Result<()> {
+    let mut socket = NftSocket::open().context("opening nftables netlink socket")?;
+    match socket.table_handle(Family::Ip, "wg_probe") {
+        Ok(_) => Ok(()),
+        Err(err) if err.is_not_found() => Ok(()),
+        Err(err) => Err(err).context("querying nftables"),
+    }
+}
+
+/// Every table `apply` may create for `interface`.
+pub fn table_names(interface: &str) -> Vec<NftTable> {
+    [
+        (Family::Ip, TABLE_V4),
+        (Family::Ip, TABLE_FILTER_V4),
+        (Family::Ip6, TABLE_V6),
+        (Family::Ip6, TABLE_FILTER_V6),
+    ]
+    .into_iter()
+    .map(|(family, prefix)| NftTable {
+        family: family.name().to_string(),
+        name: format!("{prefix}_{interface}"),
+    })
+    .collect()
+}
+
+/// Deletes the named tables that exist, returning those it deleted.
+pub fn delete_tables(tables: &[NftTable]) -> Result<Vec<NftTable>> {
+    let mut socket = NftSocket::open().context("opening nftables netlink socket")?;
+    let mut deleted = Vec::new();
+    for table in tables {
+        let family = match table.family.as_str() {
+            "ip" => Family::Ip,
+            "ip6" => Family::Ip6,
+            other => anyhow::bail!("unknown nftables family {other} for table {}", table.name),
+        };
+        let handle = match socket.table_handle(family, &table.name) {
+            Ok(handle) => handle,
+            Err(err) if err.is_not_found() => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("looking up handle of table {}", table.name));
+            }
+        };
+        socket
+            .delete_table(family, handle)
+            .with_context(|| format!("deleting table {} {}", table.family, table.name))?;
+        deleted.push(table.clone());
+    }
+    Ok(deleted)
+}
+
+async fn build_tables(configs: &[ResolvedConfig]) -> Result<Vec<Table>> {
+    let (connection, handle, _) =
+        rtnetlink::new_connection().context("opening rtnetlink connection")?;
+    tokio::spawn(connection);
+
+    let interfaces: Vec<&str> = configs
+        .iter()
+        .map(|config| config.interface.as_str())
+        .collect();
+    let mut tables = Vec::new();
+    for config in configs {
+        tables.extend(family_tables(config, &interfaces, &handle, Family::Ip).await?);
+        tables.extend(family_tables(config, &interfaces, &handle, Family::Ip6).await?);
+    }
+    Ok(tables)
+}
+
+/// The NAT and filter tables of one interface for one family, named with
+/// the interface as a suffix; the NAT table is left out when it would have
+/// nothing to do. `interfaces` are all managed interfaces, which may not
+/// forward to one another.
+async fn family_tables(
+    config: &ResolvedConfig,
+    interfaces: &[&str],
+    handle: &Handle,
+    family: Family,
+) -> Result<Vec<Table>> {
+    let (nat_table_prefix, filter_table_prefix, default) = match family {
+        Family::Ip => (TABLE_V4, TABLE_FILTER_V4, "0.0.0.0/0"),
+        Family::Ip6 => (TABLE_V6, TABLE_FILTER_V6, "::/0"),
+    };
+    let iface = &config.interface;
+    let nat_name = format!("{nat_table_prefix}_{iface}");
+    let filter_name = format!("{filter_table_prefix}_{iface}");
+    let tunnels_default = routes_default(config, default);
+    let nat = config.network.nat_mode(family == Family::Ip6);
+    let snat = snat_addr(config, family, nat)?;
+    let npt = npt_prefixes(config, family, nat)?;
+    let source_nat = tunnels_default && nat == NatMode::Masquerade;
+    let targets = port_forward::targets(config, family)?;
+
+    let mut tables = Vec::new();
+    let mut forwards = PortForwardRules::default();
+    if source_nat || npt.is_some() || !targets.is_empty() {
+        let devs = egress_devs(config, handle, family).await?;
+        forwards = port_forward::rules(&targets, &devs, iface);
+        let mut prerouting = std::mem::take(&mut forwards.prerouting);
+        let mut postrouting = Vec::new();
+        if source_nat {
+            let subnet = subnet(config, family)?;
+            for dev in &devs {
+                postrouting.push(source_nat_rule(dev, subnet, snat));
+            }
+        }
+        if let Some((internal, external)) = npt {
+            let (inbound, outbound) = npt_rules(&devs, internal, external);
+            prerouting.extend(inbound);
+            postrouting.extend(outbound);
+        }
+        postrouting.append(&mut forwards.postrouting);
+        tables.push(nat_table(family, &nat_name, prerouting, postrouting));
+    }
+
+    let peer_rules = peer_to_peer_rules(config, family)?;
+    let acl_chain = acl::acl_chain(config, family)?;
+    let mut rules = forwards.forward;
+    rules.extend(peer_rules);
+    let isolation = interfaces
+        .iter()
+        .filter(|other| **other != iface)
+        .map(|other| {
+            Rule(vec![
+                Stmt::IifName(iface.clone()),
+                Stmt::OifName(other.to_string()),
+                Stmt::Drop,
+            ])
+        })
+        .collect();
+    tables.push(forward_table(
+        family,
+        &filter_name,
+        iface,
+        isolation,
+        rules,
+        acl_chain,
+    ));
+    Ok(tables)
+}
+
+/// `egress_interfaces` if set, else the interfaces of the family's default
+/// routes in the main table.
+async fn egress_devs(
+    config: &ResolvedConfig,
+    handle: &Handle,
+    family: Family,
+) -> Result<Vec<String>> {
+    if let Some(devs) = &config.network.egress_interfaces {
+        if devs.is_empty() {
+            anyhow::bail!("egress_interfaces is empty; list at least one interface");
+        }
+        return Ok(devs.clone());
+    }
+    let devs = netlink_util::default_route_devs(handle, family == Family::Ip6)
+        .await
+        .with_context(|| format!("reading {} default routes", family.name()))?;
+    if devs.is_empty() {
+        anyhow::bail!(
+            "no {} default route to NAT through; add one or set egress_interfaces",
+            family.name()
+        );
+    }
+    Ok(devs)
+}
+
+/// The configured `snat_v4`/`snat_v6` address for `family`, if any.
+fn snat_addr(config: &ResolvedConfig, family: Family, nat: NatMode) -> Result<Option<IpAddr>> {
+    let (key, value) = match family {
+        Family::Ip => ("snat_v4", &config.network.snat_v4),
+        Family::Ip6 => ("snat_v6", &config.network.snat_v6),
+    };
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    if nat != NatMode::Masquerade {
+        anyhow::bail!(
+            "{key} only applies when {} NAT is \"masquerade\"",
+            family.name()
+        );
+    }
+    let addr: IpAddr = value
+        .parse()
+        .with_context(|| format!("parsing {key} {value}"))?;
+    if addr.is_ipv4() != (family == Family::Ip) {
+        anyhow::bail!("{key} {value} is not an {} address", family.name());
+    }
+    Ok(Some(addr))
+}
+
+/// `(subnet_v6, npt_prefix_v6)` when `family` uses NPTv6. Loading the config
+/// has already rejected `npt` for IPv4 and prefixes of different lengths.
+fn npt_prefixes(
+    config: &ResolvedConfig,
+    family: Family,
+    nat: NatMode,
+) -> Result<Option<(IpNet, IpNet)>> {
+    if nat != NatMode::Npt || family != Family::Ip6 {
+        return Ok(None);
+    }
+    let internal = subnet(config, family)?;
+    let prefix = config
+        .network
+        .npt_prefix_v6
+        .as_deref()
+        .context("nat_v6 = \"npt\" requires npt_prefix_v6")?;
+    let external: Ipv6Net = prefix
+        .parse()
+        .with_context(|| format!("parsing npt_prefix_v6 {prefix}"))?;
+    Ok(Some((internal, IpNet::V6(external.trunc()))))
+}
+
+/// Stateless NPTv6 on each egress device: `external` is mapped back onto
+/// `internal` on the way in and `internal` onto `external` on the way out.
+/// Returns the prerouting and the postrouting rules.
+fn npt_rules(devs: &[String], internal: IpNet, external: IpNet) -> (Vec<Rule>, Vec<Rule>) {
+    devs.iter()
+        .map(|dev| {
+            let inbound = Rule(vec![
+                Stmt::IifName(dev.clone()),
+                Stmt::Daddr(external),
+                Stmt::DnatPrefix(internal),
+            ]);
+            let outbound = Rule(vec![
+                Stmt::OifName(dev.clone()),
+                Stmt::Saddr(internal),
+                Stmt::SnatPrefix(external),
+            ]);
+            (inbound, outbound)
+        })
+        .unzip()
+}
+
+fn subnet(config: &ResolvedConfig, family: Family) -> Result<IpNet> {
+    match family {
+        Family::Ip => {
+            let subnet: Ipv4Net = config
+                .network
+                .subnet_v4
+                .parse()
+                .context("parsing subnet_v4 for nftables")?;
+            Ok(IpNet::V4(subnet))
+        }
+        Family::Ip6 => {
+            let subnet_v6 = config
+                .network
+                .subnet_v6
+                .as_deref()
+                .context("subnet_v6 required for ipv6 NAT")?;
+            let subnet: Ipv6Net = subnet_v6
+                .parse()
+                .context("parsing subnet_v6 for nftables")?;
+            Ok(IpNet::V6(subnet))
+        }
+    }
+}
+
+/// True if the default or any peer's AllowedIPs tunnels `default` through us.
+fn routes_default(config: &ResolvedConfig, default: &str) -> bool {
+    config.network.allowed_ips.iter().any(|ip| ip == default)
+        || config.peers.iter().any(|peer| {
+            peer.allowed_ips(&config.network)
+                .iter()
+                .any(|ip| ip == default)
+        })
+}
+
+/// Masquerades `subnet` out of `dev`, or SNATs it to `snat` when set.
+fn source_nat_rule(dev: &str, subnet: IpNet, snat: Option<IpAddr>) -> Rule {
+    Rule(vec![
+        Stmt::OifName(dev.to_string()),
+        Stmt::Saddr(subnet),
+        snat.map_or(Stmt::Masquerade, Stmt::Snat),
+    ])
+}
+
+fn nat_table(family: Family, name: &str, prerouting: Vec<Rule>, postrouting: Vec<Rule>) -> Table {
+    let mut chains = Vec::new();
+    if !prerouting.is_empty() {
+        chains.push(Chain {
+            name: CHAIN_PRE.to_string(),
+            base: Some(BaseChain {
+                kind: ChainType::Nat,
+                hook: Hook::Prerouting,
+                priority: -100,
+            }),
+            rules: prerouting,
+        });
+    }
+    chains.push(Chain {
+        name: CHAIN.to_string(),
+        base: Some(BaseChain {
+            kind: ChainType::Nat,
+            hook: Hook::Postrouting,
+            priority: 100,
+        }),
+        rules: postrouting,
+    });
+    Table {
+        family,
+        name: name.to_string(),
+        chains,
+    }
+}
+
+/// Rules for traffic between peers (in and out of the interface), placed
+/// ahead of the blanket interface accepts. Replies are always let through so
+/// that a peer allowed to reach another can hear back from it.
+fn peer_to_peer_rules(config: &ResolvedConfig, family: Family) -> Result<Vec<Rule>> {
+    let policy = config.network.peer_to_peer;
+    let exceptions: Vec<_> = config
+        .peers
+        .iter()
+        .filter(|peer| peer.peer_to_peer(&config.network) != policy)
+        .collect();
+    if policy == Policy::Allow && exceptions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let between_peers = |stmts: &[Stmt]| {
+        let mut rule = vec![
+            Stmt::IifName(config.interface.clone()),
+            Stmt::OifName(config.interface.clone()),
+        ];
+        rule.extend_from_slice(stmts);
+        Rule(rule)
+    };
+    let mut rules = vec![between_peers(&[Stmt::CtEstablished, Stmt::Accept])];
+    for peer in exceptions {
+        let verdict = match peer.peer_to_peer(&config.network) {
+            Policy::Allow => Stmt::Accept,
+            Policy::Deny => Stmt::Drop,
+        };
+        let mut sources = config
+            .lease(&peer.id)
+            .map(|lease| lease.addresses())
+            .unwrap_or_default();
+        sources.extend(peer.routed_prefixes()?);
+        for source in sources
+            .into_iter()
+            .filter(|net| acl::family_of(net) == family)
+        {
+            rules.push(between_peers(&[Stmt::Saddr(source), verdict.clone()]));
+        }
+    }
+    if policy == Policy::Deny {
+        rules.push(between_peers(&[Stmt::Drop]));
+    }
+    Ok(rules)
+}
+
+/// The forward chain: MSS clamping for TCP in and out of `iface`, the
+/// `drops` toward other managed interfaces, a jump to the ACL chain (if
+/// any), port-forward and peer-to-peer rules, then the blanket `iface`
+/// accepts. The jump comes before every accept so that no peer gets past
+/// its ACL.
+fn forward_table(
+    family: Family,
+    name: &str,
+    iface: &str,
+    drops: Vec<Rule>,
+    leading_rules: Vec<Rule>,
+    acl_chain: Option<Chain>,
+) -> Table {
+    let mut rules = vec![
+        Rule(vec![Stmt::IifName(iface.to_string()), Stmt::ClampMss]),
+        Rule(vec![Stmt::OifName(iface.to_string()), Stmt::ClampMss]),
+    ];
+    rules.extend(drops);
+    if acl_chain.is_some() {
+        rules.push(Rule(vec![
+            Stmt::IifName(iface.to_string()),
+            Stmt::Jump(acl::CHAIN_ACL.to_string()),
+        ]));
+    }
+    rules.extend(leading_rules);
+    rules.push(Rule(vec![Stmt::IifName(iface.to_string()), Stmt::Accept]));
+    rules.push(Rule(vec![Stmt::OifName(iface.to_string()), Stmt::Accept]));
+    Table {
+        family,
+        name: name.to_string(),
+        // The ACL chain must exist before the forward rule that jumps to it.
+        chains: acl_chain
+            .into_iter()
+            .chain([Chain {
+                name: CHAIN_FWD.to_string(),
+                base: Some(BaseChain {
+                    kind: ChainType::Filter,
+                    hook: Hook::Forward,
+                    priority: 0,
+                }),
+                rules,
+            }])
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_nat_and_forward_tables() {
+        let nat = nat_table(
+            Family::Ip,
+            TABLE_V4,
+            Vec::new(),
+            vec![
+                source_nat_rule("eth0", "10.66.0.0/24".parse().unwrap(), None),
+                source_nat_rule(
+                    "eth1",
+                    "10.66.0.0/24".parse().unwrap(),
+                    Some("192.0.2.7".parse().unwrap()),
+                ),
+            ],
+        );
+        assert_eq!(
+            nat.to_string(),
+            "table ip wg_nat_v4 {\n\
+             \tchain postrouting {\n\
+             \t\ttype nat hook postrouting priority 100; policy accept;\n\
+             \t\toifname \"eth0\" ip saddr 10.66.0.0/24 masquerade\n\
+             \t\toifname \"eth1\" ip saddr 10.66.0.0/24 snat to 192.0.2.7\n\
+             \t}\n\
+             }\n"
+        );
+
+        let forward = forward_table(
+            Family::Ip6,
+            TABLE_FILTER_V6,
+            "wg0",
+            Vec::new(),
+            Vec::new(),
+            None,
+        );
+        let rules: Vec<String> = forward.chains[0]
+            .rules
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "iifname \"wg0\" tcp flags syn tcp option maxseg size set rt mtu",
+                "oifname \"wg0\" tcp flags syn tcp option maxseg size set rt mtu",
+                "iifname \"wg0\" accept",
+                "oifname \"wg0\" accept",
+            ]
+        );
+    }
+
+    #[test]
+    fn restricts_peer_to_peer_traffic_with_exceptions() {
+        use crate::config::{
+            AclConfig, Lease, NetworkConfig, Paths, Peer, PeerSettings, ServerConfig,
+        };
+        use std::path::PathBuf;
+
+        let peer = |name: &str, peer_to_peer, routed: &[&str]| Peer {
+            id: format!("peer-{name}"),
+            name: Some(name.to_string()),
+            settings: PeerSettings {
+                peer_to_peer,
+                routed_prefixes: Some(routed.iter().map(ToString::to_string).collect()),
+                ..PeerSettings::default()
+            },
+        };
+        let lease = |name: &str, host: u8| Lease {
+            peer_id: format!("peer-{name}"),
+            public_key: String::new(),
+            v4: [10, 66, 0, host].into(),
+            v6: Some(format!("fd66::{host}").parse().unwrap()),
+            allocated_at: 0,
+        };
+        let config = |policy, peers| ResolvedConfig {
+            interface: "wg0".to_string(),
+            server: ServerConfig::default(),
+            network: NetworkConfig {
+                subnet_v6: Some("fd66::/64".to_string()),
+                peer_to_peer: policy,
+                ..NetworkConfig::default()
+            },
+            peers,
+            acl: AclConfig::default(),
+            forwards: Vec::new(),
+            leases: vec![lease("laptop", 2), lease("phone", 3)],
+            paths: Paths {
+                root: PathBuf::new(),
+                keys: PathBuf::new(),
+                peers: PathBuf::new(),
+                server: PathBuf::new(),
+                state: PathBuf::new(),
+                revoked: PathBuf::new(),
+            },
+        };
+        let render = |config: &ResolvedConfig, family| -> Vec<String> {
+            peer_to_peer_rules(config, family)
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+
+        let deny = config(
+            Policy::Deny,
+            vec![
+                peer("laptop", Some(Policy::Allow), &["192.168.50.0/24"]),
+                peer("phone", None, &[]),
+            ],
+        );
+        assert_eq!(
+            render(&deny, Family::Ip),
+            [
+                "iifname \"wg0\" oifname \"wg0\" ct state established,related accept",
+                "iifname \"wg0\" oifname \"wg0\" ip saddr 10.66.0.2/32 accept",
+                "iifname \"wg0\" oifname \"wg0\" ip saddr 192.168.50.0/24 accept",
+                "iifname \"wg0\" oifname \"wg0\" drop",
+            ]
+        );
+
+        let allow = config(
+            Policy::Allow,
+            vec![
+                peer("laptop", None, &[]),
+                peer("phone", Some(Policy::Deny), &[]),
+            ],
+        );
+        assert_eq!(
+            render(&allow, Family::Ip6),
+            [
+                "iifname \"wg0\" oifname \"wg0\" ct state established,related accept",
+                "iifname \"wg0\" oifname \"wg0\" ip6 saddr fd66::3/128 drop",
+            ]
+        );
+
+        let open = config(Policy::Allow, vec![peer("phone", None, &[])]);
+        assert!(render(&open, Family::Ip).is_empty());
+    }
+
+    #[test]
+    fn maps_npt_prefixes_both_ways() {
+        let devs = ["eth0".to_string(), "eth1".to_string()];
+        let (prerouting, postrouting) = npt_rules(
+            &devs,
+            "fd66::/64".parse().unwrap(),
+            "2001:db8:1::/64".parse().unwrap(),
+        );
+        let nat = nat_table(Family::Ip6, TABLE_V6, prerouting, postrouting);
+        assert_eq!(
+            nat.to_string(),
+            "table ip6 wg_nat_v6 {\n\
+             \tchain prerouting {\n\
+             \t\ttype nat hook prerouting priority -100; policy accept;\n\
+             \t\tiifname \"eth0\" ip6 daddr 2001:db8:1::/64 dnat ip6 prefix to fd66::/64\n\
+             \t\tiifname \"eth1\" ip6 daddr 2001:db8:1::/64 dnat ip6 prefix to fd66::/64\n\
+             \t}\n\
+             \tchain postrouting {\n\
+             \t\ttype nat hook postrouting priority 100; policy accept;\n\
+             \t\toifname \"eth0\" ip6 saddr fd66::/64 snat ip6 prefix to 2001:db8:1::/64\n\
+             \t\toifname \"eth1\" ip6 saddr fd66::/64 snat ip6 prefix to 2001:db8:1::/64\n\
+             \t}\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn jumps_to_acl_before_any_accept() {
+        let peer: IpNet = "10.66.0.3/32".parse().unwrap();
+        let leading = vec![
+            Rule(vec![
+                Stmt::OifName("wg0".to_string()),
+                Stmt::Daddr(peer),
+                Stmt::Dport(ruleset::L4Proto::Tcp, 22),
+                Stmt::Accept,
+            ]),
+            Rule(vec![
+                Stmt::IifName("wg0".to_string()),
+                Stmt::OifName("wg0".to_string()),
+                Stmt::CtEstablished,
+                Stmt::Accept,
+            ]),
+        ];
+        let acl_chain = Chain {
+            name: acl::CHAIN_ACL.to_string(),
+            base: None,
+            rules: vec![Rule(vec![Stmt::Saddr(peer), Stmt::Drop])],
+        };
+        let drops = vec![Rule(vec![
+            Stmt::IifName("wg0".to_string()),
+            Stmt::OifName("wg-guest".to_string()),
+            Stmt::Drop,
+        ])];
+        let forward = forward_table(
+            Family::Ip,
+            TABLE_FILTER_V4,
+            "wg0",
+            drops,
+            leading,
+            Some(acl_chain),
+        );
+        assert_eq!(forward.chains[0].name, acl::CHAIN_ACL);
+        let rules: Vec<String> = forward.chains[1]
+            .rules
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "iifname \"wg0\" tcp flags syn tcp option maxseg size set rt mtu",
+                "oifname \"wg0\" tcp flags syn tcp option maxseg size set rt mtu",
+                "iifname \"wg0\" oifname \"wg-guest\" drop",
+                "iifname \"wg0\" jump acl",
+                "oifname \"wg0\" ip daddr 10.66.0.3/32 tcp dport 22 accept",
+                "iifname \"wg0\" oifname \"wg0\" ct state established,related accept",
+                "iifname \"wg0\" accept",
+                "oifname \"wg0\" accept",
+            ]
+        );
+    }
+}
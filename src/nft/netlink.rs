@@ -0,0 +1,843 @@
+//! Minimal nf_tables netlink client: builds batched NEWTABLE/NEWCHAIN/NEWRULE
+//! transactions from a [`Table`] and sends them over `NETLINK_NETFILTER`.
+//! Every rule carries a counter, read back by [`NftSocket::rule_counters`].
+
+use crate::nft::ruleset::{BaseChain, Family, Hook, L4Proto, Rule, Stmt, Table};
+use ipnet::IpNet;
+use netlink_sys::{Socket, SocketAddr, protocols::NETLINK_NETFILTER};
+use std::fmt;
+use std::net::IpAddr;
+
+const NLMSG_HDR_LEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_CREATE: u16 = 0x400;
+const NLM_F_APPEND: u16 = 0x800;
+const NLA_F_NESTED: u16 = 0x8000;
+
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFNL_MSG_BATCH_BEGIN: u16 = 0x10;
+const NFNL_MSG_BATCH_END: u16 = 0x11;
+
+const NFT_MSG_NEWTABLE: u16 = 0;
+const NFT_MSG_GETTABLE: u16 = 1;
+const NFT_MSG_DELTABLE: u16 = 2;
+const NFT_MSG_NEWCHAIN: u16 = 3;
+const NFT_MSG_NEWRULE: u16 = 6;
+const NFT_MSG_GETRULE: u16 = 7;
+
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_TABLE_HANDLE: u16 = 4;
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_CHAIN_TYPE: u16 = 7;
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+const NFTA_DATA_VALUE: u16 = 1;
+const NFTA_COUNTER_BYTES: u16 = 1;
+const NFTA_COUNTER_PACKETS: u16 = 2;
+const NFTA_DATA_VERDICT: u16 = 2;
+const NFTA_VERDICT_CODE: u16 = 1;
+const NFTA_VERDICT_CHAIN: u16 = 2;
+
+const NFTA_META_DREG: u16 = 1;
+const NFTA_META_KEY: u16 = 2;
+const NFTA_CMP_SREG: u16 = 1;
+const NFTA_CMP_OP: u16 = 2;
+const NFTA_CMP_DATA: u16 = 3;
+const NFTA_PAYLOAD_DREG: u16 = 1;
+const NFTA_PAYLOAD_BASE: u16 = 2;
+const NFTA_PAYLOAD_OFFSET: u16 = 3;
+const NFTA_PAYLOAD_LEN: u16 = 4;
+const NFTA_BITWISE_SREG: u16 = 1;
+const NFTA_BITWISE_DREG: u16 = 2;
+const NFTA_BITWISE_LEN: u16 = 3;
+const NFTA_BITWISE_MASK: u16 = 4;
+const NFTA_BITWISE_XOR: u16 = 5;
+const NFTA_IMMEDIATE_DREG: u16 = 1;
+const NFTA_IMMEDIATE_DATA: u16 = 2;
+const NFTA_NAT_TYPE: u16 = 1;
+const NFTA_NAT_FAMILY: u16 = 2;
+const NFTA_NAT_REG_ADDR_MIN: u16 = 3;
+const NFTA_NAT_REG_ADDR_MAX: u16 = 4;
+const NFTA_NAT_REG_PROTO_MIN: u16 = 5;
+const NFTA_NAT_FLAGS: u16 = 7;
+const NFTA_CT_DREG: u16 = 1;
+const NFTA_CT_KEY: u16 = 2;
+const NFTA_RT_DREG: u16 = 1;
+const NFTA_RT_KEY: u16 = 2;
+const NFTA_BYTEORDER_SREG: u16 = 1;
+const NFTA_BYTEORDER_DREG: u16 = 2;
+const NFTA_BYTEORDER_OP: u16 = 3;
+const NFTA_BYTEORDER_LEN: u16 = 4;
+const NFTA_BYTEORDER_SIZE: u16 = 5;
+const NFTA_EXTHDR_TYPE: u16 = 2;
+const NFTA_EXTHDR_OFFSET: u16 = 3;
+const NFTA_EXTHDR_LEN: u16 = 4;
+const NFTA_EXTHDR_OP: u16 = 6;
+const NFTA_EXTHDR_SREG: u16 = 7;
+
+const NFT_META_IIFNAME: u32 = 6;
+const NFT_META_OIFNAME: u32 = 7;
+const NFT_META_L4PROTO: u32 = 16;
+const NFT_PAYLOAD_NETWORK_HEADER: u32 = 1;
+const NFT_PAYLOAD_TRANSPORT_HEADER: u32 = 2;
+const NFT_CT_STATE: u32 = 0;
+const NFT_RT_TCPMSS: u32 = 3;
+const NFT_BYTEORDER_HTON: u32 = 1;
+const NFT_EXTHDR_OP_TCPOPT: u32 = 1;
+const TCPOPT_MAXSEG: u8 = 2;
+const TCP_FLAG_SYN: u8 = 0x02;
+const NFT_CMP_EQ: u32 = 0;
+const NFT_CMP_NEQ: u32 = 1;
+const NFT_REG_VERDICT: u32 = 0;
+const NFT_REG_1: u32 = 1;
+const NFT_REG_2: u32 = 2;
+const NFT_NAT_SNAT: u32 = 0;
+const NFT_NAT_DNAT: u32 = 1;
+const NF_NAT_RANGE_NETMAP: u32 = 1 << 6;
+const NF_DROP: u32 = 0;
+const NF_ACCEPT: u32 = 1;
+const NFT_JUMP: u32 = -3i32 as u32;
+const NF_ACCEPT_POLICY: u32 = NF_ACCEPT;
+
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_IPV6: u8 = 10;
+const NF_INET_PRE_ROUTING: u32 = 0;
+const NF_INET_FORWARD: u32 = 2;
+const NF_INET_POST_ROUTING: u32 = 4;
+const IFNAMSIZ: usize = 16;
+/// `NF_CT_STATE_BIT(IP_CT_ESTABLISHED) | NF_CT_STATE_BIT(IP_CT_RELATED)`
+const CT_STATE_ESTABLISHED_RELATED: u32 = 0x2 | 0x4;
+
+/// Errors from the nf_tables netlink backend.
+#[derive(Debug)]
+pub enum NftError {
+    /// Opening or using the netlink socket failed.
+    Socket(std::io::Error),
+    /// The kernel rejected a request.
+    Kernel { op: &'static str, errno: i32 },
+    /// The kernel reply could not be parsed.
+    Malformed(&'static str),
+}
+
+impl NftError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::Kernel { errno, .. } if *errno == libc::ENOENT)
+    }
+}
+
+impl fmt::Display for NftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socket(err) => write!(f, "nftables netlink socket: {err}"),
+            Self::Kernel { op, errno } => write!(
+                f,
+                "nftables {op} rejected by kernel: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            Self::Malformed(what) => write!(f, "malformed nftables reply: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for NftError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Socket(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub struct NftSocket {
+    socket: Socket,
+    seq: u32,
+}
+
+impl NftSocket {
+    pub fn open() -> Result<Self, NftError> {
+        let mut socket = Socket::new(NETLINK_NETFILTER).map_err(NftError::Socket)?;
+        socket.bind_auto().map_err(NftError::Socket)?;
+        socket
+            .connect(&SocketAddr::new(0, 0))
+            .map_err(NftError::Socket)?;
+        Ok(Self { socket, seq: 1 })
+    }
+
+    /// Atomically replaces each table: create it (so the delete cannot fail),
+    /// delete it, then recreate it with all chains and rules.
+    pub fn replace_tables(&mut self, tables: &[Table]) -> Result<(), NftError> {
+        let mut batch = Batch::new(self.seq);
+        for table in tables {
+            batch.push_table(NFT_MSG_NEWTABLE, NLM_F_CREATE, table);
+            batch.push_table(NFT_MSG_DELTABLE, 0, table);
+            batch.push_table(NFT_MSG_NEWTABLE, NLM_F_CREATE, table);
+            for chain in &table.chains {
+                let mut attrs = Attrs::default();
+                attrs.put_str(NFTA_CHAIN_TABLE, &table.name);
+                attrs.put_str(NFTA_CHAIN_NAME, &chain.name);
+                if let Some(base) = chain.base {
+                    put_base_chain(&mut attrs, base);
+                }
+                batch.push(NFT_MSG_NEWCHAIN, NLM_F_CREATE, table.family, &attrs);
+                for rule in &chain.rules {
+                    let mut attrs = Attrs::default();
+                    attrs.put_str(NFTA_RULE_TABLE, &table.name);
+                    attrs.put_str(NFTA_RULE_CHAIN, &chain.name);
+                    attrs.nested(NFTA_RULE_EXPRESSIONS, |list| put_rule(list, rule));
+                    batch.push(
+                        NFT_MSG_NEWRULE,
+                        NLM_F_CREATE | NLM_F_APPEND,
+                        table.family,
+                        &attrs,
+                    );
+                }
+            }
+        }
+        self.commit(batch, "ruleset replace")
+    }
+
+    pub fn table_handle(&mut self, family: Family, name: &str) -> Result<u64, NftError> {
+        let mut attrs = Attrs::default();
+        attrs.put_str(NFTA_TABLE_NAME, name);
+        let seq = self.next_seq();
+        let mut buf = Vec::new();
+        put_message(
+            &mut buf,
+            msg_type(NFT_MSG_GETTABLE),
+            NLM_F_REQUEST | NLM_F_ACK,
+            seq,
+            nfproto(family),
+            0,
+            &attrs.0,
+        );
+        self.socket.send(&buf, 0).map_err(NftError::Socket)?;
+
+        let mut handle = None;
+        loop {
+            let (data, _) = self.socket.recv_from_full().map_err(NftError::Socket)?;
+            for msg in messages(&data)? {
+                match msg.kind {
+                    NLMSG_ERROR => {
+                        let errno = ack_errno(msg.payload)?;
+                        if errno != 0 {
+                            return Err(NftError::Kernel {
+                                op: "table lookup",
+                                errno,
+                            });
+                        }
+                        return handle.ok_or(NftError::Malformed("table reply without handle"));
+                    }
+                    kind if kind == msg_type(NFT_MSG_NEWTABLE) => {
+                        let attrs = msg.payload.get(4..).unwrap_or_default();
+                        handle = find_attr(attrs, NFTA_TABLE_HANDLE)
+                            .and_then(|value| value.try_into().ok())
+                            .map(u64::from_be_bytes);
+                    }
+                    NLMSG_DONE => {
+                        return handle.ok_or(NftError::Malformed("table reply without handle"));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Packet and byte counts of each rule in `table`, as `(chain, packets,
+    /// bytes)` in rule order.
+    pub fn rule_counters(
+        &mut self,
+        family: Family,
+        table: &str,
+    ) -> Result<Vec<(String, u64, u64)>, NftError> {
+        let mut attrs = Attrs::default();
+        attrs.put_str(NFTA_RULE_TABLE, table);
+        let seq = self.next_seq();
+        let mut buf = Vec::new();
+        put_message(
+            &mut buf,
+            msg_type(NFT_MSG_GETRULE),
+            NLM_F_REQUEST | NLM_F_DUMP,
+            seq,
+            nfproto(family),
+            0,
+            &attrs.0,
+        );
+        self.socket.send(&buf, 0).map_err(NftError::Socket)?;
+
+        let mut out = Vec::new();
+        loop {
+            let (data, _) = self.socket.recv_from_full().map_err(NftError::Socket)?;
+            for msg in messages(&data)? {
+                match msg.kind {
+                    NLMSG_ERROR => {
+                        let errno = ack_errno(msg.payload)?;
+                        if errno != 0 {
+                            return Err(NftError::Kernel {
+                                op: "rule dump",
+                                errno,
+                            });
+                        }
+                    }
+                    kind if kind == msg_type(NFT_MSG_NEWRULE) => {
+                        out.push(parse_rule_counter(
+                            msg.payload.get(4..).unwrap_or_default(),
+                        )?);
+                    }
+                    NLMSG_DONE => return Ok(out),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn delete_table(&mut self, family: Family, handle: u64) -> Result<(), NftError> {
+        let mut batch = Batch::new(self.seq);
+        let mut attrs = Attrs::default();
+        attrs.put(NFTA_TABLE_HANDLE, &handle.to_be_bytes());
+        batch.push(NFT_MSG_DELTABLE, 0, family, &attrs);
+        self.commit(batch, "table delete")
+    }
+
+    fn commit(&mut self, batch: Batch, op: &'static str) -> Result<(), NftError> {
+        let (buf, mut pending, next_seq) = batch.finish();
+        self.seq = next_seq;
+        self.socket.send(&buf, 0).map_err(NftError::Socket)?;
+
+        while pending > 0 {
+            let (data, _) = self.socket.recv_from_full().map_err(NftError::Socket)?;
+            for msg in messages(&data)? {
+                if msg.kind != NLMSG_ERROR {
+                    continue;
+                }
+                let errno = ack_errno(msg.payload)?;
+                if errno != 0 {
+                    return Err(NftError::Kernel { op, errno });
+                }
+                pending -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+}
+
+struct Batch {
+    buf: Vec<u8>,
+    seq: u32,
+    acks: usize,
+}
+
+impl Batch {
+    fn new(seq: u32) -> Self {
+        let mut batch = Self {
+            buf: Vec::new(),
+            seq,
+            acks: 0,
+        };
+        let seq = batch.next_seq();
+        put_message(
+            &mut batch.buf,
+            NFNL_MSG_BATCH_BEGIN,
+            NLM_F_REQUEST,
+            seq,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            &[],
+        );
+        batch
+    }
+
+    fn push_table(&mut self, msg: u16, flags: u16, table: &Table) {
+        let mut attrs = Attrs::default();
+        attrs.put_str(NFTA_TABLE_NAME, &table.name);
+        self.push(msg, flags, table.family, &attrs);
+    }
+
+    fn push(&mut self, msg: u16, flags: u16, family: Family, attrs: &Attrs) {
+        let seq = self.next_seq();
+        put_message(
+            &mut self.buf,
+            msg_type(msg),
+            NLM_F_REQUEST | NLM_F_ACK | flags,
+            seq,
+            nfproto(family),
+            0,
+            &attrs.0,
+        );
+        self.acks += 1;
+    }
+
+    fn finish(mut self) -> (Vec<u8>, usize, u32) {
+        let seq = self.next_seq();
+        put_message(
+            &mut self.buf,
+            NFNL_MSG_BATCH_END,
+            NLM_F_REQUEST,
+            seq,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            &[],
+        );
+        (self.buf, self.acks, self.seq)
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+}
+
+#[derive(Default)]
+struct Attrs(Vec<u8>);
+
+impl Attrs {
+    fn put(&mut self, kind: u16, data: &[u8]) {
+        let len = 4 + data.len();
+        self.0.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.0.extend_from_slice(&kind.to_ne_bytes());
+        self.0.extend_from_slice(data);
+        self.0.resize(align(self.0.len()), 0);
+    }
+
+    fn put_str(&mut self, kind: u16, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.put(kind, &data);
+    }
+
+    fn put_be32(&mut self, kind: u16, value: u32) {
+        self.put(kind, &value.to_be_bytes());
+    }
+
+    fn nested(&mut self, kind: u16, build: impl FnOnce(&mut Attrs)) {
+        let mut inner = Attrs::default();
+        build(&mut inner);
+        self.put(kind | NLA_F_NESTED, &inner.0);
+    }
+
+    fn expr(&mut self, name: &str, build: impl FnOnce(&mut Attrs)) {
+        self.nested(NFTA_LIST_ELEM, |elem| {
+            elem.put_str(NFTA_EXPR_NAME, name);
+            elem.nested(NFTA_EXPR_DATA, build);
+        });
+    }
+}
+
+fn put_base_chain(attrs: &mut Attrs, base: BaseChain) {
+    let hooknum = match base.hook {
+        Hook::Prerouting => NF_INET_PRE_ROUTING,
+        Hook::Forward => NF_INET_FORWARD,
+        Hook::Postrouting => NF_INET_POST_ROUTING,
+    };
+    attrs.nested(NFTA_CHAIN_HOOK, |hook| {
+        hook.put_be32(NFTA_HOOK_HOOKNUM, hooknum);
+        hook.put_be32(NFTA_HOOK_PRIORITY, base.priority as u32);
+    });
+    attrs.put_be32(NFTA_CHAIN_POLICY, NF_ACCEPT_POLICY);
+    attrs.put_str(NFTA_CHAIN_TYPE, base.kind.name());
+}
+
+/// Emits the rule's expressions with a counter ahead of its first action, or
+/// at the end if it only matches and mangles.
+fn put_rule(list: &mut Attrs, rule: &Rule) {
+    let mut counted = false;
+    for stmt in &rule.0 {
+        if !counted && is_action(stmt) {
+            list.expr("counter", |_| {});
+            counted = true;
+        }
+        match stmt {
+            Stmt::IifName(name) => put_ifname(list, NFT_META_IIFNAME, name),
+            Stmt::OifName(name) => put_ifname(list, NFT_META_OIFNAME, name),
+            Stmt::Saddr(net) => put_addr(list, net, AddrField::Source),
+            Stmt::Daddr(net) => put_addr(list, net, AddrField::Destination),
+            Stmt::L4Proto(proto) => put_l4proto(list, *proto),
+            Stmt::Dport(proto, port) => put_dport(list, *proto, *port),
+            Stmt::CtEstablished => put_ct_established(list),
+            Stmt::ClampMss => put_clamp_mss(list),
+            Stmt::Masquerade => list.expr("masq", |_| {}),
+            Stmt::Snat(addr) => put_nat(list, NFT_NAT_SNAT, *addr, None),
+            Stmt::Dnat(addr, port) => put_nat(list, NFT_NAT_DNAT, *addr, Some(*port)),
+            Stmt::SnatPrefix(net) => put_nat_prefix(list, NFT_NAT_SNAT, net),
+            Stmt::DnatPrefix(net) => put_nat_prefix(list, NFT_NAT_DNAT, net),
+            Stmt::Accept => put_verdict(list, NF_ACCEPT),
+            Stmt::Drop => put_verdict(list, NF_DROP),
+            Stmt::Jump(chain) => put_jump(list, chain),
+        }
+    }
+    if !counted {
+        list.expr("counter", |_| {});
+    }
+}
+
+fn is_action(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Masquerade
+            | Stmt::Snat(_)
+            | Stmt::Dnat(..)
+            | Stmt::SnatPrefix(_)
+            | Stmt::DnatPrefix(_)
+            | Stmt::Accept
+            | Stmt::Drop
+            | Stmt::Jump(_)
+    )
+}
+
+fn put_ifname(list: &mut Attrs, key: u32, name: &str) {
+    list.expr("meta", |meta| {
+        meta.put_be32(NFTA_META_KEY, key);
+        meta.put_be32(NFTA_META_DREG, NFT_REG_1);
+    });
+    let mut data = [0u8; IFNAMSIZ];
+    let len = name.len().min(IFNAMSIZ - 1);
+    data[..len].copy_from_slice(&name.as_bytes()[..len]);
+    put_cmp_eq(list, &data);
+}
+
+enum AddrField {
+    Source,
+    Destination,
+}
+
+fn put_addr(list: &mut Attrs, net: &IpNet, field: AddrField) {
+    let (offset, addr, mask) = match net {
+        IpNet::V4(v4) => (
+            12,
+            v4.network().octets().to_vec(),
+            v4.netmask().octets().to_vec(),
+        ),
+        IpNet::V6(v6) => (
+            8,
+            v6.network().octets().to_vec(),
+            v6.netmask().octets().to_vec(),
+        ),
+    };
+    // The destination address directly follows the source address.
+    let offset = match field {
+        AddrField::Source => offset,
+        AddrField::Destination => offset + addr.len() as u32,
+    };
+    let len = addr.len() as u32;
+    list.expr("payload", |payload| {
+        payload.put_be32(NFTA_PAYLOAD_DREG, NFT_REG_1);
+        payload.put_be32(NFTA_PAYLOAD_BASE, NFT_PAYLOAD_NETWORK_HEADER);
+        payload.put_be32(NFTA_PAYLOAD_OFFSET, offset);
+        payload.put_be32(NFTA_PAYLOAD_LEN, len);
+    });
+    if net.prefix_len() < net.max_prefix_len() {
+        list.expr("bitwise", |bitwise| {
+            bitwise.put_be32(NFTA_BITWISE_SREG, NFT_REG_1);
+            bitwise.put_be32(NFTA_BITWISE_DREG, NFT_REG_1);
+            bitwise.put_be32(NFTA_BITWISE_LEN, len);
+            bitwise.nested(NFTA_BITWISE_MASK, |data| data.put(NFTA_DATA_VALUE, &mask));
+            bitwise.nested(NFTA_BITWISE_XOR, |data| {
+                data.put(NFTA_DATA_VALUE, &vec![0; mask.len()]);
+            });
+        });
+    }
+    put_cmp_eq(list, &addr);
+}
+
+fn put_l4proto(list: &mut Attrs, proto: L4Proto) {
+    list.expr("meta", |meta| {
+        meta.put_be32(NFTA_META_KEY, NFT_META_L4PROTO);
+        meta.put_be32(NFTA_META_DREG, NFT_REG_1);
+    });
+    put_cmp_eq(list, &[proto.number()]);
+}
+
+/// TCP and UDP both carry the destination port at offset 2.
+fn put_dport(list: &mut Attrs, proto: L4Proto, port: u16) {
+    put_l4proto(list, proto);
+    list.expr("payload", |payload| {
+        payload.put_be32(NFTA_PAYLOAD_DREG, NFT_REG_1);
+        payload.put_be32(NFTA_PAYLOAD_BASE, NFT_PAYLOAD_TRANSPORT_HEADER);
+        payload.put_be32(NFTA_PAYLOAD_OFFSET, 2);
+        payload.put_be32(NFTA_PAYLOAD_LEN, 2);
+    });
+    put_cmp_eq(list, &port.to_be_bytes());
+}
+
+/// Conntrack state is a host-endian bitmask: mask it and test for non-zero.
+fn put_ct_established(list: &mut Attrs) {
+    list.expr("ct", |ct| {
+        ct.put_be32(NFTA_CT_KEY, NFT_CT_STATE);
+        ct.put_be32(NFTA_CT_DREG, NFT_REG_1);
+    });
+    list.expr("bitwise", |bitwise| {
+        bitwise.put_be32(NFTA_BITWISE_SREG, NFT_REG_1);
+        bitwise.put_be32(NFTA_BITWISE_DREG, NFT_REG_1);
+        bitwise.put_be32(NFTA_BITWISE_LEN, 4);
+        bitwise.nested(NFTA_BITWISE_MASK, |data| {
+            data.put(NFTA_DATA_VALUE, &CT_STATE_ESTABLISHED_RELATED.to_ne_bytes());
+        });
+        bitwise.nested(NFTA_BITWISE_XOR, |data| data.put(NFTA_DATA_VALUE, &[0; 4]));
+    });
+    put_cmp(list, NFT_CMP_NEQ, &[0; 4]);
+}
+
+/// `tcp flags syn tcp option maxseg size set rt mtu`: on SYNs, lowers the
+/// MSS option to what the route's MTU allows (the kernel never raises it).
+fn put_clamp_mss(list: &mut Attrs) {
+    put_l4proto(list, L4Proto::Tcp);
+    list.expr("payload", |payload| {
+        payload.put_be32(NFTA_PAYLOAD_DREG, NFT_REG_1);
+        payload.put_be32(NFTA_PAYLOAD_BASE, NFT_PAYLOAD_TRANSPORT_HEADER);
+        payload.put_be32(NFTA_PAYLOAD_OFFSET, 13);
+        payload.put_be32(NFTA_PAYLOAD_LEN, 1);
+    });
+    list.expr("bitwise", |bitwise| {
+        bitwise.put_be32(NFTA_BITWISE_SREG, NFT_REG_1);
+        bitwise.put_be32(NFTA_BITWISE_DREG, NFT_REG_1);
+        bitwise.put_be32(NFTA_BITWISE_LEN, 1);
+        bitwise.nested(NFTA_BITWISE_MASK, |data| {
+            data.put(NFTA_DATA_VALUE, &[TCP_FLAG_SYN])
+        });
+        bitwise.nested(NFTA_BITWISE_XOR, |data| data.put(NFTA_DATA_VALUE, &[0]));
+    });
+    put_cmp(list, NFT_CMP_NEQ, &[0]);
+    // The route MSS is host-endian; the option is written in network order.
+    list.expr("rt", |rt| {
+        rt.put_be32(NFTA_RT_KEY, NFT_RT_TCPMSS);
+        rt.put_be32(NFTA_RT_DREG, NFT_REG_1);
+    });
+    list.expr("byteorder", |byteorder| {
+        byteorder.put_be32(NFTA_BYTEORDER_SREG, NFT_REG_1);
+        byteorder.put_be32(NFTA_BYTEORDER_DREG, NFT_REG_1);
+        byteorder.put_be32(NFTA_BYTEORDER_OP, NFT_BYTEORDER_HTON);
+        byteorder.put_be32(NFTA_BYTEORDER_LEN, 2);
+        byteorder.put_be32(NFTA_BYTEORDER_SIZE, 2);
+    });
+    list.expr("exthdr", |exthdr| {
+        exthdr.put(NFTA_EXTHDR_TYPE, &[TCPOPT_MAXSEG]);
+        exthdr.put_be32(NFTA_EXTHDR_OFFSET, 2);
+        exthdr.put_be32(NFTA_EXTHDR_LEN, 2);
+        exthdr.put_be32(NFTA_EXTHDR_OP, NFT_EXTHDR_OP_TCPOPT);
+        exthdr.put_be32(NFTA_EXTHDR_SREG, NFT_REG_1);
+    });
+}
+
+fn put_cmp_eq(list: &mut Attrs, value: &[u8]) {
+    put_cmp(list, NFT_CMP_EQ, value);
+}
+
+fn put_cmp(list: &mut Attrs, op: u32, value: &[u8]) {
+    list.expr("cmp", |cmp| {
+        cmp.put_be32(NFTA_CMP_SREG, NFT_REG_1);
+        cmp.put_be32(NFTA_CMP_OP, op);
+        cmp.nested(NFTA_CMP_DATA, |data| data.put(NFTA_DATA_VALUE, value));
+    });
+}
+
+/// Loads the target address into reg 1 and the port (if any) into reg 2,
+/// then NATs to them; the kernel infers the range flags from which registers
+/// are set.
+fn put_nat(list: &mut Attrs, kind: u32, addr: IpAddr, port: Option<u16>) {
+    let (family, octets) = match addr {
+        IpAddr::V4(v4) => (NFPROTO_IPV4, v4.octets().to_vec()),
+        IpAddr::V6(v6) => (NFPROTO_IPV6, v6.octets().to_vec()),
+    };
+    put_immediate(list, NFT_REG_1, &octets);
+    if let Some(port) = port {
+        put_immediate(list, NFT_REG_2, &port.to_be_bytes());
+    }
+    list.expr("nat", |nat| {
+        nat.put_be32(NFTA_NAT_TYPE, kind);
+        nat.put_be32(NFTA_NAT_FAMILY, u32::from(family));
+        nat.put_be32(NFTA_NAT_REG_ADDR_MIN, NFT_REG_1);
+        if port.is_some() {
+            nat.put_be32(NFTA_NAT_REG_PROTO_MIN, NFT_REG_2);
+        }
+    });
+}
+
+/// Maps addresses onto `net` keeping their host bits: the first and last
+/// addresses of `net` go in regs 1 and 2 as the range, with the netmap flag.
+fn put_nat_prefix(list: &mut Attrs, kind: u32, net: &IpNet) {
+    let (family, first, last) = match net {
+        IpNet::V4(v4) => (
+            NFPROTO_IPV4,
+            v4.network().octets().to_vec(),
+            v4.broadcast().octets().to_vec(),
+        ),
+        IpNet::V6(v6) => (
+            NFPROTO_IPV6,
+            v6.network().octets().to_vec(),
+            v6.broadcast().octets().to_vec(),
+        ),
+    };
+    put_immediate(list, NFT_REG_1, &first);
+    put_immediate(list, NFT_REG_2, &last);
+    list.expr("nat", |nat| {
+        nat.put_be32(NFTA_NAT_TYPE, kind);
+        nat.put_be32(NFTA_NAT_FAMILY, u32::from(family));
+        nat.put_be32(NFTA_NAT_REG_ADDR_MIN, NFT_REG_1);
+        nat.put_be32(NFTA_NAT_REG_ADDR_MAX, NFT_REG_2);
+        nat.put_be32(NFTA_NAT_FLAGS, NF_NAT_RANGE_NETMAP);
+    });
+}
+
+fn put_immediate(list: &mut Attrs, reg: u32, value: &[u8]) {
+    list.expr("immediate", |imm| {
+        imm.put_be32(NFTA_IMMEDIATE_DREG, reg);
+        imm.nested(NFTA_IMMEDIATE_DATA, |data| data.put(NFTA_DATA_VALUE, value));
+    });
+}
+
+fn put_jump(list: &mut Attrs, chain: &str) {
+    list.expr("immediate", |imm| {
+        imm.put_be32(NFTA_IMMEDIATE_DREG, NFT_REG_VERDICT);
+        imm.nested(NFTA_IMMEDIATE_DATA, |data| {
+            data.nested(NFTA_DATA_VERDICT, |verdict| {
+                verdict.put_be32(NFTA_VERDICT_CODE, NFT_JUMP);
+                verdict.put_str(NFTA_VERDICT_CHAIN, chain);
+            });
+        });
+    });
+}
+
+fn put_verdict(list: &mut Attrs, code: u32) {
+    list.expr("immediate", |imm| {
+        imm.put_be32(NFTA_IMMEDIATE_DREG, NFT_REG_VERDICT);
+        imm.nested(NFTA_IMMEDIATE_DATA, |data| {
+            data.nested(NFTA_DATA_VERDICT, |verdict| {
+                verdict.put_be32(NFTA_VERDICT_CODE, code);
+            });
+        });
+    });
+}
+
+fn put_message(
+    buf: &mut Vec<u8>,
+    kind: u16,
+    flags: u16,
+    seq: u32,
+    family: u8,
+    res_id: u16,
+    attrs: &[u8],
+) {
+    let len = NLMSG_HDR_LEN + 4 + attrs.len();
+    buf.extend_from_slice(&(len as u32).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.push(family);
+    buf.push(0);
+    buf.extend_from_slice(&res_id.to_be_bytes());
+    buf.extend_from_slice(attrs);
+}
+
+fn msg_type(msg: u16) -> u16 {
+    (NFNL_SUBSYS_NFTABLES << 8) | msg
+}
+
+fn nfproto(family: Family) -> u8 {
+    match family {
+        Family::Ip => NFPROTO_IPV4,
+        Family::Ip6 => NFPROTO_IPV6,
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+struct Message<'a> {
+    kind: u16,
+    payload: &'a [u8],
+}
+
+fn messages(data: &[u8]) -> Result<Vec<Message<'_>>, NftError> {
+    let mut out = Vec::new();
+    let mut rest = data;
+    while rest.len() >= NLMSG_HDR_LEN {
+        let len = u32::from_ne_bytes(rest[0..4].try_into().unwrap_or_default()) as usize;
+        if len < NLMSG_HDR_LEN || len > rest.len() {
+            return Err(NftError::Malformed("netlink message length"));
+        }
+        let kind = u16::from_ne_bytes([rest[4], rest[5]]);
+        out.push(Message {
+            kind,
+            payload: &rest[NLMSG_HDR_LEN..len],
+        });
+        rest = &rest[align(len).min(rest.len())..];
+    }
+    Ok(out)
+}
+
+fn ack_errno(payload: &[u8]) -> Result<i32, NftError> {
+    let code = payload
+        .get(0..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i32::from_ne_bytes)
+        .ok_or(NftError::Malformed("netlink error message"))?;
+    Ok(-code)
+}
+
+/// The chain and counter of a NEWRULE message; rules without a counter (not
+/// created by us) report zeros.
+fn parse_rule_counter(attrs: &[u8]) -> Result<(String, u64, u64), NftError> {
+    let chain = find_attr(attrs, NFTA_RULE_CHAIN)
+        .map(|name| {
+            String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .ok_or(NftError::Malformed("rule without chain"))?;
+    let counter = find_attr(attrs, NFTA_RULE_EXPRESSIONS)
+        .into_iter()
+        .flat_map(iter_attrs)
+        .filter(|(kind, _)| *kind == NFTA_LIST_ELEM)
+        .find(|(_, expr)| find_attr(expr, NFTA_EXPR_NAME) == Some(b"counter\0"))
+        .and_then(|(_, expr)| find_attr(expr, NFTA_EXPR_DATA));
+    let read = |kind| {
+        counter
+            .and_then(|data| find_attr(data, kind))
+            .and_then(|value| value.try_into().ok())
+            .map_or(0, u64::from_be_bytes)
+    };
+    Ok((chain, read(NFTA_COUNTER_PACKETS), read(NFTA_COUNTER_BYTES)))
+}
+
+fn find_attr(attrs: &[u8], kind: u16) -> Option<&[u8]> {
+    iter_attrs(attrs).find_map(|(attr_kind, value)| (attr_kind == kind).then_some(value))
+}
+
+/// Walks a run of netlink attributes as `(kind, value)`, stopping at the
+/// first malformed one.
+fn iter_attrs(mut attrs: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if attrs.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]) & !NLA_F_NESTED;
+        if len < 4 || len > attrs.len() {
+            return None;
+        }
+        let value = &attrs[4..len];
+        attrs = &attrs[align(len).min(attrs.len())..];
+        Some((kind, value))
+    })
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::config::ResolvedConfig;
+use crate::nft::acl::l4proto;
+use crate::nft::ruleset::{Family, L4Proto, Rule, Stmt};
+
+/// A `[[forward]]` entry resolved to the peer's address in one family.
+pub(super) struct Target {
+    proto: L4Proto,
+    external_port: u16,
+    addr: IpAddr,
+    peer_port: u16,
+    /// Set when the peer does not send its default route through the tunnel,
+    /// so replies only find their way back if the source is rewritten.
+    masquerade: bool,
+}
+
+#[derive(Default)]
+pub(super) struct PortForwardRules {
+    pub prerouting: Vec<Rule>,
+    pub postrouting: Vec<Rule>,
+    pub forward: Vec<Rule>,
+}
+
+pub(super) fn targets(config: &ResolvedConfig, family: Family) -> Result<Vec<Target>> {
+    let default = match family {
+        Family::Ip => "0.0.0.0/0",
+        Family::Ip6 => "::/0",
+    };
+    let mut out = Vec::new();
+    for forward in &config.forwards {
+        let lease = config
+            .lease(&forward.peer)
+            .with_context(|| format!("forward target {} has no address lease", forward.peer))?;
+        let addr = match family {
+            Family::Ip => Some(IpAddr::V4(lease.v4)),
+            Family::Ip6 => lease.v6.map(IpAddr::V6),
+        };
+        let Some(addr) = addr else {
+            continue;
+        };
+        let full_tunnel = config
+            .peers
+            .iter()
+            .find(|peer| peer.id == forward.peer)
+            .is_some_and(|peer| {
+                peer.allowed_ips(&config.network)
+                    .iter()
+                    .any(|ip| ip == default)
+            });
+        out.push(Target {
+            proto: l4proto(forward.proto),
+            external_port: forward.external_port,
+            addr,
+            peer_port: forward.peer_port(),
+            masquerade: !full_tunnel,
+        });
+    }
+    Ok(out)
+}
+
+/// DNAT on the way in through each of `devs`, accept toward the peer on
+/// `iface` only from those devices, so other peers get no way around
+/// `peer_to_peer` or their ACL, and masquerade toward peers that would
+/// otherwise answer outside the tunnel. Postrouting has no input device, so
+/// the masquerade rule matches on the output side alone.
+pub(super) fn rules(targets: &[Target], devs: &[String], iface: &str) -> PortForwardRules {
+    let mut rules = PortForwardRules::default();
+    for target in targets {
+        let peer = IpNet::from(target.addr);
+        let toward_peer = |verdict: Stmt| {
+            vec![
+                Stmt::OifName(iface.to_string()),
+                Stmt::Daddr(peer),
+                Stmt::Dport(target.proto, target.peer_port),
+                verdict,
+            ]
+        };
+        for dev in devs {
+            rules.prerouting.push(Rule(vec![
+                Stmt::IifName(dev.clone()),
+                Stmt::Dport(target.proto, target.external_port),
+                Stmt::Dnat(target.addr, target.peer_port),
+            ]));
+            let mut accept = vec![Stmt::IifName(dev.clone())];
+            accept.extend(toward_peer(Stmt::Accept));
+            rules.forward.push(Rule(accept));
+        }
+        if target.masquerade {
+            rules.postrouting.push(Rule(toward_peer(Stmt::Masquerade)));
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_forwarded_traffic_only_from_egress_devices() {
+        let targets = [Target {
+            proto: L4Proto::Tcp,
+            external_port: 2222,
+            addr: "10.66.0.3".parse().unwrap(),
+            peer_port: 22,
+            masquerade: true,
+        }];
+        let devs = ["eth0".to_string(), "eth1".to_string()];
+        let rules = rules(&targets, &devs, "wg0");
+        let render =
+            |rules: &[Rule]| -> Vec<String> { rules.iter().map(ToString::to_string).collect() };
+        assert_eq!(
+            render(&rules.prerouting),
+            [
+                "iifname \"eth0\" tcp dport 2222 dnat to 10.66.0.3:22",
+                "iifname \"eth1\" tcp dport 2222 dnat to 10.66.0.3:22",
+            ]
+        );
+        assert_eq!(
+            render(&rules.forward),
+            [
+                "iifname \"eth0\" oifname \"wg0\" ip daddr 10.66.0.3/32 tcp dport 22 accept",
+                "iifname \"eth1\" oifname \"wg0\" ip daddr 10.66.0.3/32 tcp dport 22 accept",
+            ]
+        );
+        assert_eq!(
+            render(&rules.postrouting),
+            ["oifname \"wg0\" ip daddr 10.66.0.3/32 tcp dport 22 masquerade"]
+        );
+    }
+}
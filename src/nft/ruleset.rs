@@ -0,0 +1,191 @@
+use ipnet::IpNet;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Ip,
+    Ip6,
+}
+
+impl Family {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ip => "ip",
+            Self::Ip6 => "ip6",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub family: Family,
+    pub name: String,
+    pub chains: Vec<Chain>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub name: String,
+    pub base: Option<BaseChain>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BaseChain {
+    pub kind: ChainType,
+    pub hook: Hook,
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChainType {
+    Filter,
+    Nat,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    Prerouting,
+    Forward,
+    Postrouting,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule(pub Vec<Stmt>);
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    IifName(String),
+    OifName(String),
+    Saddr(IpNet),
+    Daddr(IpNet),
+    /// `meta l4proto <proto>`
+    L4Proto(L4Proto),
+    /// `<proto> dport <port>`, which implies the protocol match.
+    Dport(L4Proto, u16),
+    /// `ct state established,related`
+    CtEstablished,
+    /// `tcp flags syn tcp option maxseg size set rt mtu`
+    ClampMss,
+    Masquerade,
+    /// `snat to <addr>`
+    Snat(IpAddr),
+    /// `dnat to <addr>:<port>`
+    Dnat(IpAddr, u16),
+    /// `snat ip6 prefix to <net>`: rewrites the network bits of the source.
+    SnatPrefix(IpNet),
+    /// `dnat ip6 prefix to <net>`
+    DnatPrefix(IpNet),
+    Accept,
+    Drop,
+    Jump(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L4Proto {
+    Tcp,
+    Udp,
+}
+
+impl L4Proto {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+
+    /// IANA protocol number, as found in `meta l4proto`.
+    pub fn number(self) -> u8 {
+        match self {
+            Self::Tcp => 6,
+            Self::Udp => 17,
+        }
+    }
+}
+
+impl ChainType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Filter => "filter",
+            Self::Nat => "nat",
+        }
+    }
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Prerouting => "prerouting",
+            Self::Forward => "forward",
+            Self::Postrouting => "postrouting",
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "table {} {} {{", self.family.name(), self.name)?;
+        for chain in &self.chains {
+            writeln!(f, "\tchain {} {{", chain.name)?;
+            if let Some(base) = chain.base {
+                writeln!(
+                    f,
+                    "\t\ttype {} hook {} priority {}; policy accept;",
+                    base.kind.name(),
+                    base.hook.name(),
+                    base.priority
+                )?;
+            }
+            for rule in &chain.rules {
+                writeln!(f, "\t\t{rule}")?;
+            }
+            writeln!(f, "\t}}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, stmt) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{stmt}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IifName(name) => write!(f, "iifname \"{name}\""),
+            Self::OifName(name) => write!(f, "oifname \"{name}\""),
+            Self::Saddr(net) => write!(f, "{} saddr {net}", addr_keyword(net)),
+            Self::Daddr(net) => write!(f, "{} daddr {net}", addr_keyword(net)),
+            Self::L4Proto(proto) => write!(f, "meta l4proto {}", proto.name()),
+            Self::Dport(proto, port) => write!(f, "{} dport {port}", proto.name()),
+            Self::CtEstablished => f.write_str("ct state established,related"),
+            Self::ClampMss => f.write_str("tcp flags syn tcp option maxseg size set rt mtu"),
+            Self::Masquerade => f.write_str("masquerade"),
+            Self::Snat(addr) => write!(f, "snat to {addr}"),
+            Self::Dnat(IpAddr::V4(addr), port) => write!(f, "dnat to {addr}:{port}"),
+            Self::Dnat(IpAddr::V6(addr), port) => write!(f, "dnat to [{addr}]:{port}"),
+            Self::SnatPrefix(net) => write!(f, "snat {} prefix to {net}", addr_keyword(net)),
+            Self::DnatPrefix(net) => write!(f, "dnat {} prefix to {net}", addr_keyword(net)),
+            Self::Accept => f.write_str("accept"),
+            Self::Drop => f.write_str("drop"),
+            Self::Jump(chain) => write!(f, "jump {chain}"),
+        }
+    }
+}
+
+fn addr_keyword(net: &IpNet) -> &'static str {
+    match net {
+        IpNet::V4(_) => "ip",
+        IpNet::V6(_) => "ip6",
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::config::{self, Journal, LeaseChange, Locations, PlannedFile};
+use crate::{nft, wg_iface};
+
+/// Everything `run` or `generate` would do, as `--dry-run` reports it.
+#[derive(Serialize)]
+struct Plan {
+    /// Journals of an earlier run whose leftovers `run` removes first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recover: Vec<Journal>,
+    interfaces: Vec<InterfaceEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    nftables: Vec<nft::TablePlan>,
+}
+
+#[derive(Serialize)]
+struct InterfaceEntry {
+    interface: String,
+    files: Vec<PlannedFile>,
+    leases: Vec<LeaseChange>,
+    /// New `count` peer IDs made up for the plan; a real run picks others.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    random_peer_ids: Vec<String>,
+    /// Only planned for `run`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<wg_iface::InterfacePlan>,
+}
+
+/// Prints what `rust-wg run` would write, allocate and set up.
+pub async fn run(locations: &Locations, json: bool) -> Result<()> {
+    show(locations, true, json).await
+}
+
+/// Prints what `rust-wg generate` would write and allocate.
+pub async fn generate(locations: &Locations, json: bool) -> Result<()> {
+    show(locations, false, json).await
+}
+
+async fn show(locations: &Locations, system: bool, json: bool) -> Result<()> {
+    let planned = config::plan(locations)?;
+    let mut plan = Plan {
+        recover: Vec::new(),
+        interfaces: Vec::new(),
+        nftables: Vec::new(),
+    };
+    if system {
+        plan.recover = config::recorded_journals(locations)?
+            .into_iter()
+            .map(|(_, journal)| journal)
+            .collect();
+        let configs: Vec<_> = planned.iter().map(|(config, _)| config.clone()).collect();
+        plan.nftables = nft::plan(&configs).await?;
+    }
+    for (config, generated) in planned {
+        let link = if system {
+            Some(wg_iface::plan(&config).await?)
+        } else {
+            None
+        };
+        plan.interfaces.push(InterfaceEntry {
+            interface: config.interface,
+            files: generated.files,
+            leases: generated.leases,
+            random_peer_ids: generated.random_ids,
+            link,
+        });
+    }
+
+    if json {
+        let text = serde_json::to_string_pretty(&plan).context("serializing plan")?;
+        println!("{text}");
+    } else {
+        print!("{}", render(&plan));
+    }
+    Ok(())
+}
+
+fn render(plan: &Plan) -> String {
+    let mut out = String::new();
+    for journal in &plan.recover {
+        out.push_str(&format!(
+            "recover: remove leftovers of an earlier run on {} ({} routes, {} tables)\n",
+            journal.interface,
+            journal.routes.len(),
+            journal.nft_tables.len()
+        ));
+    }
+    for entry in &plan.interfaces {
+        out.push_str(&format!("{}:\n", entry.interface));
+        if !entry.random_peer_ids.is_empty() {
+            out.push_str(&format!(
+                "  note: {} are random `count` peer IDs; a real run picks other ones\n",
+                entry.random_peer_ids.join(", ")
+            ));
+        }
+        if entry.files.is_empty() && entry.leases.is_empty() {
+            out.push_str("  files and leases unchanged\n");
+        }
+        for file in &entry.files {
+            let secret = if file.secret { " (key material)" } else { "" };
+            out.push_str(&format!(
+                "  {:<8} {}{secret}\n",
+                file.action,
+                file.path.display()
+            ));
+            for line in file.diff.iter().flat_map(|diff| diff.lines()) {
+                out.push_str(format!("{:11}{line}", "").trim_end());
+                out.push('\n');
+            }
+        }
+        for lease in &entry.leases {
+            let line = match (lease.previous.is_empty(), lease.addresses.is_empty()) {
+                (true, _) => format!("allocate {} {}", lease.peer_id, lease.addresses.join(", ")),
+                (_, true) => format!("release  {} {}", lease.peer_id, lease.previous.join(", ")),
+                _ => format!(
+                    "move     {} {} (was {})",
+                    lease.peer_id,
+                    lease.addresses.join(", "),
+                    lease.previous.join(", ")
+                ),
+            };
+            out.push_str(&format!("  {line}\n"));
+        }
+        if let Some(link) = &entry.link {
+            let action = if link.exists { "reuse" } else { "create" };
+            let mtu = link
+                .mtu
+                .map(|mtu| format!(", mtu {mtu}"))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "  link     {action} wireguard link {}, listen port {}{mtu}\n",
+                entry.interface, link.listen_port
+            ));
+            for addr in &link.addresses {
+                out.push_str(&format!("  address  {addr}\n"));
+            }
+            for peer in &link.peers {
+                out.push_str(&format!(
+                    "  peer     {} allowed-ips {}\n",
+                    peer.peer_id,
+                    peer.allowed_ips.join(", ")
+                ));
+            }
+            for route in &link.routes {
+                out.push_str(&format!("  route    {route}\n"));
+            }
+        }
+    }
+    if !plan.nftables.is_empty() {
+        out.push_str("nftables:\n");
+        for table in &plan.nftables {
+            for line in table.script.lines() {
+                out.push_str(&format!("  {line}\n"));
+            }
+        }
+    }
+    out
+}
@@ -1,24 +1,366 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::fs;
+use std::path::PathBuf;
 
-use crate::{config, module_check, nft, shutdown, wg_iface};
+use crate::config::{Locations, ResolvedConfig};
+use crate::shutdown::Signal;
+use crate::{config, events, metrics, module_check, nft, shutdown, wg_iface};
 
-pub async fn run() -> Result<()> {
+pub async fn run(locations: &Locations) -> Result<()> {
+    let lock = config::lock_run(locations)?;
+    let mut signals = shutdown::SignalListener::new()?;
     module_check::ensure_wireguard_support().await?;
-    let resolved = config::prepare()?;
-    let wg_handle = wg_iface::apply(&resolved).await?;
-    let nft_handles = nft::apply(&resolved)?;
-    shutdown::wait_for_signal().await?;
+    let metrics = match config::metrics_listen(locations)? {
+        Some(listen) => Some(metrics::Metrics::serve(listen).await?),
+        None => None,
+    };
+    let events = events::Events::start(config::events_config(locations)?)?;
+    let mut resolved = config::prepare(locations)?;
+    recover(locations, &lock).await?;
+    let mut wg_handles = Vec::new();
+    for config in &resolved {
+        record(config, None)?;
+        wg_handles.push(wg_iface::apply(config).await?);
+    }
+    let mut nft_handles = nft::apply(&resolved).await?;
+    if let Some(metrics) = &metrics {
+        metrics.update(&resolved, &nft_handles);
+    }
+    events.update(&resolved);
+
+    while let Signal::Reload = signals.wait().await? {
+        eprintln!("reload: SIGHUP received, re-reading configuration");
+        match reload(locations, &resolved, &mut wg_handles, &mut nft_handles).await {
+            Ok(updated) => {
+                resolved = updated;
+                if let Some(metrics) = &metrics {
+                    metrics.update(&resolved, &nft_handles);
+                }
+                events.update(&resolved);
+                eprintln!("reload: done");
+            }
+            Err(err) => eprintln!("reload: failed: {err:#}"),
+        }
+    }
+
     nft::teardown(&nft_handles)?;
-    wg_iface::teardown(&resolved, wg_handle).await?;
+    for handle in wg_handles {
+        wg_iface::teardown(handle).await?;
+    }
+    for config in &resolved {
+        config::remove_journal(&config.paths.state)?;
+    }
+
+    Ok(())
+}
+
+/// Removes whatever journals show a previous run left behind, e.g. after it
+/// was killed before it could tear down. Taking the run lock proves that run
+/// is gone.
+async fn recover(locations: &Locations, _lock: &config::RunLock) -> Result<()> {
+    for (state_dir, journal) in config::recorded_journals(locations)? {
+        eprintln!(
+            "run: a previous run did not tear down {}; cleaning up",
+            journal.interface
+        );
+        for removed in remove_recorded(&journal).await? {
+            eprintln!("run: removed {removed}");
+        }
+        config::remove_journal(&state_dir)?;
+    }
+    Ok(())
+}
+
+/// Journals what applying `config` may create, including the routes of the
+/// `previous` configuration it replaces.
+fn record(config: &ResolvedConfig, previous: Option<&ResolvedConfig>) -> Result<()> {
+    let mut routes = wg_iface::planned_routes(config)?;
+    if let Some(previous) = previous {
+        for route in wg_iface::planned_routes(previous)? {
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+    }
+    let journal = config::Journal {
+        interface: config.interface.clone(),
+        routes: routes.iter().map(ToString::to_string).collect(),
+        nft_tables: nft::table_names(&config.interface),
+    };
+    config::write_journal(&config.paths.state, &journal)
+}
+
+/// Tears down what `journal` records, describing each thing it removed.
+async fn remove_recorded(journal: &config::Journal) -> Result<Vec<String>> {
+    let routes = journal
+        .routes
+        .iter()
+        .map(|route| {
+            route
+                .parse::<IpNet>()
+                .with_context(|| format!("parsing journaled route {route}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut removed = Vec::new();
+    if wg_iface::remove_stale(&journal.interface, &routes).await? {
+        removed.push(format!("interface {}", journal.interface));
+    }
+    for table in nft::delete_tables(&journal.nft_tables)? {
+        removed.push(format!("nftables table {} {}", table.family, table.name));
+    }
+    Ok(removed)
+}
+
+/// The `recorded` journals with their state dirs, plus a journal of every
+/// table `run` may create for each `configured` interface that has none.
+fn cleanup_targets(
+    recorded: Vec<(PathBuf, config::Journal)>,
+    configured: Vec<String>,
+) -> Vec<(Option<PathBuf>, config::Journal)> {
+    let mut journals: Vec<_> = recorded
+        .into_iter()
+        .map(|(state_dir, journal)| (Some(state_dir), journal))
+        .collect();
+    for name in configured {
+        if !journals
+            .iter()
+            .any(|(_, journal)| journal.interface == name)
+        {
+            let journal = config::Journal {
+                nft_tables: nft::table_names(&name),
+                interface: name,
+                routes: Vec::new(),
+            };
+            journals.push((None, journal));
+        }
+    }
+    journals
+}
+
+/// Reloads interfaces that stay configured, brings up new ones and tears
+/// down those that were removed. The nftables tables are built before any
+/// interface is touched; if applying fails part way, the interfaces changed
+/// so far are put back as `current` has them.
+async fn reload(
+    locations: &Locations,
+    current: &[ResolvedConfig],
+    wg_handles: &mut Vec<wg_iface::WgHandles>,
+    nft_handles: &mut nft::NftHandles,
+) -> Result<Vec<ResolvedConfig>> {
+    let updated = config::prepare(locations)?;
+    let tables = nft::build(&updated).await?;
+    let mut touched = 0;
+    if let Err(err) = apply_reload(current, &updated, wg_handles, &mut touched)
+        .await
+        .and_then(|()| nft::reload(nft_handles, tables))
+    {
+        return Err(
+            match roll_back(current, &updated[..touched], wg_handles).await {
+                Ok(()) => err.context("previous configuration restored"),
+                Err(rollback) => err.context(format!(
+                    "restoring the previous configuration failed too ({rollback:#})"
+                )),
+            },
+        );
+    }
+    while let Some(idx) = wg_handles
+        .iter()
+        .position(|h| !updated.iter().any(|c| c.interface == h.name))
+    {
+        let handle = wg_handles.remove(idx);
+        let name = handle.name.clone();
+        eprintln!("reload: removing interface {name}");
+        let state_dir = current
+            .iter()
+            .find(|c| c.interface == name)
+            .map(|c| c.paths.state.clone());
+        // The new configuration is in effect by now; an interface that
+        // cannot be removed keeps its journal for the next run to clean up.
+        if let Err(err) = wg_iface::teardown(handle).await {
+            eprintln!("reload: removing interface {name} failed: {err:#}");
+        } else if let Some(state_dir) = state_dir {
+            config::remove_journal(&state_dir)?;
+        }
+    }
+    Ok(updated)
+}
+
+/// Applies `updated` to the interfaces one by one, counting in `touched`
+/// those it has started to change.
+async fn apply_reload(
+    current: &[ResolvedConfig],
+    updated: &[ResolvedConfig],
+    wg_handles: &mut Vec<wg_iface::WgHandles>,
+    touched: &mut usize,
+) -> Result<()> {
+    for config in updated {
+        let previous = current.iter().find(|c| c.interface == config.interface);
+        let handle = wg_handles.iter_mut().find(|h| h.name == config.interface);
+        record(config, previous)?;
+        *touched += 1;
+        match handle {
+            // Without a previous configuration the link was brought up by a
+            // reload that failed later on; diff against what it was given.
+            Some(handle) => wg_iface::reload(previous.unwrap_or(config), config, handle).await?,
+            None => wg_handles.push(wg_iface::apply(config).await?),
+        }
+    }
+    Ok(())
+}
 
+/// Undoes a failed reload of the `touched` interfaces: those `current` has
+/// are reloaded back to it, those it brought up are torn down again.
+async fn roll_back(
+    current: &[ResolvedConfig],
+    touched: &[ResolvedConfig],
+    wg_handles: &mut Vec<wg_iface::WgHandles>,
+) -> Result<()> {
+    for config in touched.iter().rev() {
+        let previous = current.iter().find(|c| c.interface == config.interface);
+        let handle = wg_handles.iter().position(|h| h.name == config.interface);
+        match (previous, handle) {
+            (Some(previous), Some(idx)) => {
+                wg_iface::reload(config, previous, &mut wg_handles[idx]).await?;
+                record(previous, None)?;
+            }
+            (Some(previous), None) => {
+                wg_handles.push(wg_iface::apply(previous).await?);
+                record(previous, None)?;
+            }
+            (None, Some(idx)) => {
+                wg_iface::teardown(wg_handles.remove(idx)).await?;
+                config::remove_journal(&config.paths.state)?;
+            }
+            (None, None) => {
+                wg_iface::remove_stale(&config.interface, &wg_iface::planned_routes(config)?)
+                    .await?;
+                config::remove_journal(&config.paths.state)?;
+            }
+        }
+    }
     Ok(())
 }
 
-pub fn show_peer(_peers: Vec<String>) {
-    // TODO: implement peer QR output and config lookup.
+pub fn show_peer(
+    locations: &Locations,
+    peers: &[String],
+    interface: Option<&str>,
+    conf_only: bool,
+    qr_only: bool,
+) -> Result<()> {
+    let confs = peers
+        .iter()
+        .map(|peer| config::peer_conf_path(locations, peer, interface))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (idx, conf) in confs.iter().enumerate() {
+        if idx > 0 {
+            println!();
+        }
+        if !qr_only {
+            let text =
+                fs::read_to_string(conf).with_context(|| format!("reading {}", conf.display()))?;
+            print!("{text}");
+        }
+        if !conf_only {
+            config::print_qr(conf)?;
+        }
+    }
+    Ok(())
 }
 
-pub fn generate() -> Result<()> {
-    let _ = config::prepare()?;
+pub fn generate(locations: &Locations) -> Result<()> {
+    let _ = config::prepare(locations)?;
     Ok(())
 }
+
+/// Prints every problem `config::check` finds, failing if there are any.
+pub fn check_config(locations: &Locations) -> Result<()> {
+    let (path, problems) = config::check(locations)?;
+    for problem in &problems {
+        println!("{problem}");
+    }
+    match problems.len() {
+        0 => {
+            println!("config: {} is valid", path.display());
+            Ok(())
+        }
+        1 => anyhow::bail!("1 problem in the configuration"),
+        n => anyhow::bail!("{n} problems in the configuration"),
+    }
+}
+
+/// Removes every interface and nftables table journaled by `run`, plus those
+/// of the configured interfaces in case a journal is missing.
+pub async fn cleanup(locations: &Locations) -> Result<()> {
+    let _lock = config::lock_run(locations)?;
+    let configured = config::interface_names(locations).unwrap_or_else(|err| {
+        eprintln!("cleanup: skipping configured interfaces: {err:#}");
+        Vec::new()
+    });
+    let journals = cleanup_targets(config::recorded_journals(locations)?, configured);
+
+    let mut removed_any = false;
+    for (state_dir, journal) in journals {
+        for removed in remove_recorded(&journal).await? {
+            println!("removed {removed}");
+            removed_any = true;
+        }
+        if let Some(state_dir) = state_dir {
+            config::remove_journal(&state_dir)?;
+        }
+    }
+    if !removed_any {
+        println!("nothing to clean up");
+    }
+    Ok(())
+}
+
+pub async fn revoke_peer(
+    locations: &Locations,
+    peer: &str,
+    interface: Option<&str>,
+    delete: bool,
+) -> Result<()> {
+    let pending = config::pending_revocation(locations, peer, interface)?;
+    if wg_iface::remove_peer(&pending.interface, &pending.public_key, &pending.addresses).await? {
+        println!("removed {} from {}", pending.peer_id, pending.interface);
+    }
+    let revoked = config::revoke_peer(&pending, delete)?;
+    match &revoked.archive {
+        Some(archive) => println!(
+            "revoked {}; keys archived in {}",
+            revoked.peer_id,
+            archive.display()
+        ),
+        None => println!("revoked {}; keys deleted", revoked.peer_id),
+    }
+    println!("send SIGHUP to a running `rust-wg run` to rewrite server.conf");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_up_journaled_and_configured_interfaces_once() {
+        let recorded = vec![(
+            PathBuf::from("/var/lib/wg/state"),
+            config::Journal {
+                interface: "wg0".to_string(),
+                routes: vec!["10.66.0.2/32".to_string()],
+                nft_tables: Vec::new(),
+            },
+        )];
+        let targets = cleanup_targets(recorded, vec!["wg0".to_string(), "wg-guest".to_string()]);
+
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].0, Some(PathBuf::from("/var/lib/wg/state")));
+        assert_eq!(targets[0].1.routes, ["10.66.0.2/32"]);
+        assert_eq!(targets[1].0, None);
+        assert_eq!(targets[1].1.interface, "wg-guest");
+        assert_eq!(targets[1].1.nft_tables, nft::table_names("wg-guest"));
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+
+use crate::config::{self, EffectiveConfig, Locations, Setting};
+
+/// Prints the effective configuration as TOML, or JSON, with the source of
+/// every value.
+pub fn run(locations: &Locations, json: bool) -> Result<()> {
+    let effective = config::effective(locations)?;
+    if json {
+        let text = serde_json::to_string_pretty(&effective).context("serializing config")?;
+        println!("{text}");
+    } else {
+        print!("{}", render(&effective));
+    }
+    Ok(())
+}
+
+fn render(effective: &EffectiveConfig) -> String {
+    let mut out = format!(
+        "# Effective configuration from {}, WG_* overrides and defaults,\n\
+         # with the source of each value.\n",
+        effective.config.display()
+    );
+    if let Setting::Table(entries) = &effective.settings {
+        render_table(&mut out, "", entries, false);
+    }
+    out
+}
+
+/// Writes the values of a table, then its sections under their own headers.
+fn render_table(out: &mut String, path: &str, entries: &[(String, Setting)], array: bool) {
+    let lines: Vec<(String, &str)> = entries
+        .iter()
+        .filter_map(|(key, setting)| match setting {
+            Setting::Value { value, source } => Some((assignment(key, value), source.as_str())),
+            _ => None,
+        })
+        .collect();
+    if array {
+        out.push_str(&format!("\n[[{path}]]\n"));
+    } else if !path.is_empty() && !lines.is_empty() {
+        out.push_str(&format!("\n[{path}]\n"));
+    }
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in &lines {
+        out.push_str(&format!("{line:width$}  # {source}\n"));
+    }
+    for (key, setting) in entries {
+        let child = if path.is_empty() {
+            bare_key(key)
+        } else {
+            format!("{path}.{}", bare_key(key))
+        };
+        match setting {
+            Setting::Value { .. } => {}
+            Setting::Table(entries) => render_table(out, &child, entries, false),
+            Setting::Tables(items) => {
+                for item in items {
+                    if let Setting::Table(entries) = item {
+                        render_table(out, &child, entries, true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `key = value`, commented out for options left unset.
+fn assignment(key: &str, value: &serde_json::Value) -> String {
+    match toml::Value::try_from(value) {
+        Ok(value) => format!("{} = {value}", bare_key(key)),
+        Err(_) => format!("# {} = (unset)", bare_key(key)),
+    }
+}
+
+fn bare_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if bare {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
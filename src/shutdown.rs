@@ -1,25 +1,54 @@
 use anyhow::Result;
 
-pub async fn wait_for_signal() -> Result<()> {
+pub enum Signal {
+    Shutdown,
+    Reload,
+}
+
+/// Signal handlers are registered once so a SIGHUP arriving while a reload is
+/// in progress is queued rather than falling back to the default action.
+pub struct SignalListener {
+    #[cfg(unix)]
+    term: tokio::signal::unix::Signal,
     #[cfg(unix)]
-    {
-        use tokio::signal::unix::{SignalKind, signal};
+    int: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    hup: tokio::signal::unix::Signal,
+}
 
-        let mut term = signal(SignalKind::terminate())?;
-        let mut int = signal(SignalKind::interrupt())?;
-        let mut hup = signal(SignalKind::hangup())?;
+impl SignalListener {
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
 
-        tokio::select! {
-            _ = term.recv() => {}
-            _ = int.recv() => {}
-            _ = hup.recv() => {}
+            Ok(Self {
+                term: signal(SignalKind::terminate())?,
+                int: signal(SignalKind::interrupt())?,
+                hup: signal(SignalKind::hangup())?,
+            })
         }
-    }
 
-    #[cfg(not(unix))]
-    {
-        tokio::signal::ctrl_c().await?;
+        #[cfg(not(unix))]
+        {
+            Ok(Self {})
+        }
     }
 
-    Ok(())
+    pub async fn wait(&mut self) -> Result<Signal> {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                _ = self.term.recv() => Ok(Signal::Shutdown),
+                _ = self.int.recv() => Ok(Signal::Shutdown),
+                _ = self.hup.recv() => Ok(Signal::Reload),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            tokio::signal::ctrl_c().await?;
+            Ok(Signal::Shutdown)
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{self, Locations};
+use crate::wg_iface;
+
+/// One device peer, joined with the peer directory it was generated from.
+#[derive(Serialize)]
+struct PeerRow {
+    interface: String,
+    /// `None` for peers on the device that no peer directory knows about.
+    peer_id: Option<String>,
+    name: Option<String>,
+    public_key: String,
+    endpoint: Option<String>,
+    /// Unix time of the latest handshake; `None` if there has not been one.
+    latest_handshake: Option<u64>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    persistent_keepalive: Option<u16>,
+}
+
+/// Prints the live peers of every configured interface as a table, or as a
+/// JSON array with `json`.
+pub async fn show(locations: &Locations, json: bool) -> Result<()> {
+    let mut rows = Vec::new();
+    for (interface, records) in config::generated_peers(locations)? {
+        let Some(peers) = wg_iface::peer_status(&interface).await? else {
+            eprintln!("status: interface {interface} is not running");
+            continue;
+        };
+        for peer in peers {
+            let record = config::find_record(&records, &peer.public_key);
+            rows.push(PeerRow {
+                interface: interface.clone(),
+                peer_id: record.map(|record| record.id.clone()),
+                name: record.and_then(|record| record.name.clone()),
+                public_key: peer.public_key,
+                endpoint: peer.endpoint.map(|addr| addr.to_string()),
+                latest_handshake: peer
+                    .last_handshake
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs()),
+                rx_bytes: peer.rx_bytes,
+                tx_bytes: peer.tx_bytes,
+                persistent_keepalive: peer.persistent_keepalive,
+            });
+        }
+    }
+
+    if json {
+        let text = serde_json::to_string_pretty(&rows).context("serializing status")?;
+        println!("{text}");
+    } else {
+        print_table(&rows);
+    }
+    Ok(())
+}
+
+fn print_table(rows: &[PeerRow]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let mut table = vec![
+        [
+            "INTERFACE",
+            "PEER",
+            "NAME",
+            "ENDPOINT",
+            "HANDSHAKE",
+            "RX",
+            "TX",
+            "KEEPALIVE",
+        ]
+        .map(String::from),
+    ];
+    for row in rows {
+        table.push([
+            row.interface.clone(),
+            row.peer_id
+                .clone()
+                .unwrap_or_else(|| format!("({}...)", &row.public_key[..8])),
+            row.name.clone().unwrap_or_else(|| "-".to_string()),
+            row.endpoint.clone().unwrap_or_else(|| "-".to_string()),
+            match row.latest_handshake {
+                Some(at) => format!("{} ago", format_duration(now.saturating_sub(at))),
+                None => "never".to_string(),
+            },
+            format_bytes(row.rx_bytes),
+            format_bytes(row.tx_bytes),
+            row.persistent_keepalive
+                .map_or_else(|| "off".to_string(), |secs| format!("{secs}s")),
+        ]);
+    }
+
+    let mut widths = [0; 8];
+    for line in &table {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for line in &table {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// The two most significant units, e.g. `3m 12s` or `2d 4h`.
+fn format_duration(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{mins}m {}s", secs % 60),
+        (0, _, _) => format!("{hours}h {mins}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_and_sizes() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(192), "3m 12s");
+        assert_eq!(format_duration(7_500), "2h 5m");
+        assert_eq!(format_duration(187_200), "2d 4h");
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1_536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -1,34 +1,73 @@
 use crate::config::ResolvedConfig;
-use crate::wg_iface::util::ignore_exists;
+use crate::wg_iface::util::{ignore_exists, ignore_notfound};
 use anyhow::{Context, Result};
+use futures::TryStreamExt;
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
-use std::net::IpAddr;
 
 pub(super) async fn configure_addresses(
     handle: &rtnetlink::Handle,
     link_index: u32,
     config: &ResolvedConfig,
 ) -> Result<()> {
-    let (v4, v6) = server_addresses(config)?;
-    let mut addrs = vec![IpNet::new(IpAddr::V4(v4), 32)?];
-    if let Some(addr) = v6 {
-        addrs.push(IpNet::new(IpAddr::V6(addr), 128)?);
+    for addr in address_list(config)? {
+        add_address(handle, link_index, addr).await?;
     }
+    Ok(())
+}
 
-    for addr in addrs {
-        let res = handle
+/// Swaps the interface addresses when the subnet or `assign_subnet_prefix`
+/// changed; new addresses are added before the old ones are removed.
+pub(super) async fn reload_addresses(
+    handle: &rtnetlink::Handle,
+    link_index: u32,
+    current: &ResolvedConfig,
+    updated: &ResolvedConfig,
+) -> Result<()> {
+    let before = address_list(current)?;
+    let after = address_list(updated)?;
+    for addr in after.iter().filter(|addr| !before.contains(addr)) {
+        add_address(handle, link_index, *addr).await?;
+    }
+    for addr in before.iter().filter(|addr| !after.contains(addr)) {
+        let mut existing = handle
             .address()
-            .add(link_index, addr.addr(), addr.prefix_len())
-            .execute()
-            .await;
-        ignore_exists(res).with_context(|| format!("adding address {addr}"))?;
+            .get()
+            .set_link_index_filter(link_index)
+            .set_address_filter(addr.addr())
+            .set_prefix_length_filter(addr.prefix_len())
+            .execute();
+        while let Some(message) = existing
+            .try_next()
+            .await
+            .with_context(|| format!("looking up address {addr}"))?
+        {
+            let res = handle.address().del(message).execute().await;
+            ignore_notfound(res).with_context(|| format!("deleting address {addr}"))?;
+        }
     }
     Ok(())
 }
 
-fn server_addresses(
-    config: &ResolvedConfig,
-) -> Result<(std::net::Ipv4Addr, Option<std::net::Ipv6Addr>)> {
+async fn add_address(handle: &rtnetlink::Handle, link_index: u32, addr: IpNet) -> Result<()> {
+    let res = handle
+        .address()
+        .add(link_index, addr.addr(), addr.prefix_len())
+        .execute()
+        .await;
+    ignore_exists(res).with_context(|| format!("adding address {addr}"))
+}
+
+pub(super) fn address_list(config: &ResolvedConfig) -> Result<Vec<IpNet>> {
+    let (v4, v6) = server_addresses(config)?;
+    let mut addrs = vec![IpNet::V4(v4)];
+    addrs.extend(v6.map(IpNet::V6));
+    Ok(addrs)
+}
+
+/// The server's interface addresses: host prefixes by default, or the
+/// subnet prefixes when `assign_subnet_prefix` is set.
+fn server_addresses(config: &ResolvedConfig) -> Result<(Ipv4Net, Option<Ipv6Net>)> {
+    let full = config.network.assign_subnet_prefix;
     let v4_net: Ipv4Net = config
         .network
         .subnet_v4
@@ -38,16 +77,19 @@ fn server_addresses(
     let server_v4 = v4_hosts
         .next()
         .context("subnet_v4 has no usable host address")?;
+    let server_v4 = Ipv4Net::new(server_v4, if full { v4_net.prefix_len() } else { 32 })?;
 
     let server_v6 = match config.network.subnet_v6.as_deref() {
         Some(value) => {
             let v6_net: Ipv6Net = value.parse().context("parsing subnet_v6")?;
             let mut v6_hosts = v6_net.hosts();
-            Some(
-                v6_hosts
-                    .next()
-                    .context("subnet_v6 has no usable host address")?,
-            )
+            let addr = v6_hosts
+                .next()
+                .context("subnet_v6 has no usable host address")?;
+            Some(Ipv6Net::new(
+                addr,
+                if full { v6_net.prefix_len() } else { 128 },
+            )?)
         }
         None => None,
     };
@@ -0,0 +1,293 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use genetlink::GenetlinkHandle;
+use ipnet::IpNet;
+use netlink_packet_core::{
+    NLM_F_ACK, NLM_F_DUMP, NLM_F_REQUEST, NetlinkHeader, NetlinkMessage, NetlinkPayload,
+};
+use netlink_packet_generic::GenlMessage;
+use netlink_packet_wireguard::{
+    WireguardAddressFamily, WireguardAllowedIp, WireguardAllowedIpAttr, WireguardAttribute,
+    WireguardCmd, WireguardDeviceFlags, WireguardMessage, WireguardPeer, WireguardPeerAttribute,
+    WireguardPeerFlags,
+};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Peers per WG_CMD_SET_DEVICE message; keeps each message well below the
+/// netlink socket buffer, mirroring how `wg setconf` splits large configs.
+const PEERS_PER_MESSAGE: usize = 128;
+
+pub type Key = [u8; 32];
+
+pub struct DeviceConfig {
+    pub private_key: Key,
+    pub listen_port: u16,
+    pub peers: Vec<PeerConfig>,
+}
+
+pub struct PeerConfig {
+    pub public_key: Key,
+    pub preshared_key: Option<Key>,
+    pub allowed_ips: Vec<IpNet>,
+}
+
+pub struct DeviceInfo {
+    pub listen_port: u16,
+    pub peers: Vec<PeerInfo>,
+}
+
+pub struct PeerInfo {
+    pub public_key: Key,
+    pub preshared_key: Option<Key>,
+    pub allowed_ips: Vec<IpNet>,
+    pub endpoint: Option<SocketAddr>,
+    /// `None` until the first handshake completes.
+    pub last_handshake: Option<SystemTime>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Keepalive interval in seconds; `None` when disabled.
+    pub persistent_keepalive: Option<u16>,
+}
+
+pub enum PeerChange {
+    /// Add the peer, or update its PSK and allowed IPs in place.
+    Upsert(PeerConfig),
+    Remove(Key),
+}
+
+/// Replaces the device's private key, listen port and full peer list.
+pub async fn set_device(iface: &str, config: &DeviceConfig) -> Result<()> {
+    let mut handle = connect()?;
+
+    let device = vec![
+        WireguardAttribute::IfName(iface.to_string()),
+        WireguardAttribute::PrivateKey(config.private_key),
+        WireguardAttribute::ListenPort(config.listen_port),
+        WireguardAttribute::Flags(WireguardDeviceFlags::ReplacePeers),
+    ];
+    let peers: Vec<WireguardPeer> = config.peers.iter().map(peer_attributes).collect();
+    set_in_chunks(&mut handle, iface, device, peers).await
+}
+
+/// Applies peer additions, updates and removals without touching other peers,
+/// so their sessions and handshakes survive.
+pub async fn update_device(
+    iface: &str,
+    listen_port: Option<u16>,
+    changes: &[PeerChange],
+) -> Result<()> {
+    let mut handle = connect()?;
+    let mut device = vec![WireguardAttribute::IfName(iface.to_string())];
+    if let Some(port) = listen_port {
+        device.push(WireguardAttribute::ListenPort(port));
+    }
+    let peers = changes
+        .iter()
+        .map(|change| match change {
+            PeerChange::Upsert(peer) => peer_attributes(peer),
+            PeerChange::Remove(key) => WireguardPeer(vec![
+                WireguardPeerAttribute::PublicKey(*key),
+                WireguardPeerAttribute::Flags(WireguardPeerFlags::RemoveMe),
+            ]),
+        })
+        .collect();
+    set_in_chunks(&mut handle, iface, device, peers).await
+}
+
+/// Removes every peer and releases the listen port.
+pub async fn clear_device(iface: &str) -> Result<()> {
+    let mut handle = connect()?;
+    let attrs = vec![
+        WireguardAttribute::IfName(iface.to_string()),
+        WireguardAttribute::ListenPort(0),
+        WireguardAttribute::Flags(WireguardDeviceFlags::ReplacePeers),
+    ];
+    set(&mut handle, attrs).await
+}
+
+pub async fn get_device(iface: &str) -> Result<DeviceInfo> {
+    let mut handle = connect()?;
+    let mut header = NetlinkHeader::default();
+    header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+    let message = NetlinkMessage::new(
+        header,
+        GenlMessage::from_payload(WireguardMessage {
+            cmd: WireguardCmd::GetDevice,
+            attributes: vec![WireguardAttribute::IfName(iface.to_string())],
+        })
+        .into(),
+    );
+
+    let mut info = DeviceInfo {
+        listen_port: 0,
+        peers: Vec::new(),
+    };
+    let mut responses = handle
+        .request(message)
+        .await
+        .with_context(|| format!("querying wireguard device {iface}"))?;
+    while let Some(response) = responses.next().await {
+        let response = response.context("decoding wireguard device reply")?;
+        match response.payload {
+            NetlinkPayload::InnerMessage(genl) => {
+                for attr in genl.payload.attributes {
+                    match attr {
+                        WireguardAttribute::ListenPort(port) => info.listen_port = port,
+                        // Large peer lists are split across several dump
+                        // messages; each carries the next batch of peers.
+                        WireguardAttribute::Peers(peers) => {
+                            info.peers.extend(peers.iter().filter_map(parse_peer));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            NetlinkPayload::Error(err) if err.code.is_some() => {
+                return Err(err.to_io())
+                    .with_context(|| format!("querying wireguard device {iface}"));
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn connect() -> Result<GenetlinkHandle> {
+    let (connection, handle, _) = genetlink::new_connection().context("opening genetlink")?;
+    tokio::spawn(connection);
+    Ok(handle)
+}
+
+/// Sends `device` with the first batch of peers, then the remaining peers in
+/// follow-up messages that only name the interface.
+async fn set_in_chunks(
+    handle: &mut GenetlinkHandle,
+    iface: &str,
+    mut device: Vec<WireguardAttribute>,
+    peers: Vec<WireguardPeer>,
+) -> Result<()> {
+    let mut chunks = peers.chunks(PEERS_PER_MESSAGE);
+    if let Some(first) = chunks.next() {
+        device.push(WireguardAttribute::Peers(first.to_vec()));
+    }
+    set(handle, device).await?;
+
+    for chunk in chunks {
+        let attrs = vec![
+            WireguardAttribute::IfName(iface.to_string()),
+            WireguardAttribute::Peers(chunk.to_vec()),
+        ];
+        set(handle, attrs).await?;
+    }
+    Ok(())
+}
+
+async fn set(handle: &mut GenetlinkHandle, attributes: Vec<WireguardAttribute>) -> Result<()> {
+    let mut header = NetlinkHeader::default();
+    header.flags = NLM_F_REQUEST | NLM_F_ACK;
+    let message = NetlinkMessage::new(
+        header,
+        GenlMessage::from_payload(WireguardMessage {
+            cmd: WireguardCmd::SetDevice,
+            attributes,
+        })
+        .into(),
+    );
+
+    let mut responses = handle
+        .request(message)
+        .await
+        .context("sending wireguard set device")?;
+    while let Some(response) = responses.next().await {
+        let response = response.context("decoding wireguard set device reply")?;
+        if let NetlinkPayload::Error(err) = response.payload
+            && err.code.is_some()
+        {
+            return Err(err.to_io()).context("configuring wireguard device");
+        }
+    }
+    Ok(())
+}
+
+fn peer_attributes(peer: &PeerConfig) -> WireguardPeer {
+    let mut attrs = vec![
+        WireguardPeerAttribute::PublicKey(peer.public_key),
+        WireguardPeerAttribute::Flags(WireguardPeerFlags::ReplaceAllowedIps),
+    ];
+    if let Some(psk) = peer.preshared_key {
+        attrs.push(WireguardPeerAttribute::PresharedKey(psk));
+    }
+    attrs.push(WireguardPeerAttribute::AllowedIps(
+        peer.allowed_ips.iter().map(allowed_ip).collect(),
+    ));
+    WireguardPeer(attrs)
+}
+
+fn allowed_ip(net: &IpNet) -> WireguardAllowedIp {
+    let family = match net {
+        IpNet::V4(_) => WireguardAddressFamily::Ipv4,
+        IpNet::V6(_) => WireguardAddressFamily::Ipv6,
+    };
+    WireguardAllowedIp(vec![
+        WireguardAllowedIpAttr::Family(family),
+        WireguardAllowedIpAttr::IpAddr(net.addr()),
+        WireguardAllowedIpAttr::Cidr(net.prefix_len()),
+    ])
+}
+
+fn parse_peer(peer: &WireguardPeer) -> Option<PeerInfo> {
+    let mut public_key = None;
+    let mut info = PeerInfo {
+        public_key: [0; 32],
+        preshared_key: None,
+        allowed_ips: Vec::new(),
+        endpoint: None,
+        last_handshake: None,
+        rx_bytes: 0,
+        tx_bytes: 0,
+        persistent_keepalive: None,
+    };
+    for attr in peer.iter() {
+        match attr {
+            WireguardPeerAttribute::PublicKey(key) => public_key = Some(*key),
+            // The kernel reports an all-zero key when no PSK is set.
+            WireguardPeerAttribute::PresharedKey(key) if *key != [0; 32] => {
+                info.preshared_key = Some(*key);
+            }
+            WireguardPeerAttribute::AllowedIps(ips) => {
+                info.allowed_ips
+                    .extend(ips.iter().filter_map(parse_allowed_ip));
+            }
+            WireguardPeerAttribute::Endpoint(addr) => info.endpoint = Some(*addr),
+            // A zero timestamp means no handshake yet.
+            WireguardPeerAttribute::LastHandshake(time) if time.seconds > 0 => {
+                info.last_handshake = Some(
+                    UNIX_EPOCH
+                        + Duration::new(time.seconds as u64, time.nano_seconds.max(0) as u32),
+                );
+            }
+            WireguardPeerAttribute::RxBytes(bytes) => info.rx_bytes = *bytes,
+            WireguardPeerAttribute::TxBytes(bytes) => info.tx_bytes = *bytes,
+            WireguardPeerAttribute::PersistentKeepalive(secs) if *secs > 0 => {
+                info.persistent_keepalive = Some(*secs);
+            }
+            _ => {}
+        }
+    }
+    info.public_key = public_key?;
+    Some(info)
+}
+
+fn parse_allowed_ip(ip: &WireguardAllowedIp) -> Option<IpNet> {
+    let mut addr = None;
+    let mut cidr = None;
+    for attr in ip.iter() {
+        match attr {
+            WireguardAllowedIpAttr::IpAddr(value) => addr = Some(*value),
+            WireguardAllowedIpAttr::Cidr(value) => cidr = Some(*value),
+            _ => {}
+        }
+    }
+    IpNet::new(addr?, cidr?).ok()
+}
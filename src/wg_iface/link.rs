@@ -1,21 +1,23 @@
 use crate::netlink_util::get_link_by_name;
-use crate::wg_iface::WG_IFACE;
 use anyhow::{Context, Result};
 use rtnetlink::{LinkUnspec, LinkWireguard};
 
-pub(super) async fn ensure_wireguard_link(handle: &rtnetlink::Handle) -> Result<u32> {
-    if let Some(link) = get_link_by_name(handle, WG_IFACE).await? {
+/// The MTU the kernel gives a new WireGuard link.
+pub(super) const DEFAULT_MTU: u16 = 1420;
+
+pub(super) async fn ensure_wireguard_link(handle: &rtnetlink::Handle, name: &str) -> Result<u32> {
+    if let Some(link) = get_link_by_name(handle, name).await? {
         return Ok(link.header.index);
     }
 
     handle
         .link()
-        .add(LinkWireguard::new(WG_IFACE).build())
+        .add(LinkWireguard::new(name).build())
         .execute()
         .await
-        .context("creating wireguard link")?;
+        .with_context(|| format!("creating wireguard link {name}"))?;
 
-    let link = get_link_by_name(handle, WG_IFACE)
+    let link = get_link_by_name(handle, name)
         .await?
         .context("wireguard link missing after creation")?;
 
@@ -30,3 +32,20 @@ pub(super) async fn set_link_up(handle: &rtnetlink::Handle, link_index: u32) ->
         .await
         .context("setting wg link up")
 }
+
+pub(super) async fn set_link_mtu(
+    handle: &rtnetlink::Handle,
+    link_index: u32,
+    mtu: u16,
+) -> Result<()> {
+    handle
+        .link()
+        .set(
+            LinkUnspec::new_with_index(link_index)
+                .mtu(u32::from(mtu))
+                .build(),
+        )
+        .execute()
+        .await
+        .with_context(|| format!("setting wg link mtu to {mtu}"))
+}
@@ -1,44 +1,218 @@
 use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::SystemTime;
 
 use crate::config::ResolvedConfig;
 
 mod addresses;
+mod device;
 mod link;
 mod peers;
 mod routes;
 mod util;
 
-pub const WG_IFACE: &str = "wg0";
+/// Live state of one peer on a running interface.
+pub struct PeerStatus {
+    pub public_key: String,
+    pub endpoint: Option<SocketAddr>,
+    pub last_handshake: Option<SystemTime>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// What `apply` would set up for one interface.
+#[derive(Serialize)]
+pub struct InterfacePlan {
+    /// The link exists already and would be reused.
+    pub exists: bool,
+    pub listen_port: u16,
+    pub mtu: Option<u16>,
+    pub addresses: Vec<String>,
+    pub peers: Vec<PeerPlan>,
+    pub routes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PeerPlan {
+    pub peer_id: String,
+    pub allowed_ips: Vec<String>,
+}
 
 pub struct WgHandles {
+    pub name: String,
     pub link_index: u32,
+    routes: Vec<IpNet>,
 }
 
 pub async fn apply(config: &ResolvedConfig) -> Result<WgHandles> {
     let (connection, handle, _) = rtnetlink::new_connection().context("opening netlink")?;
     tokio::spawn(connection);
 
-    eprintln!("wg: creating interface {WG_IFACE}");
-    let link_index = link::ensure_wireguard_link(&handle).await?;
-    eprintln!("wg: interface {WG_IFACE} index {link_index}");
+    let name = &config.interface;
+    eprintln!("wg: creating interface {name}");
+    let link_index = link::ensure_wireguard_link(&handle, name).await?;
+    eprintln!("wg: interface {name} index {link_index}");
     eprintln!("wg: assigning interface addresses");
     addresses::configure_addresses(&handle, link_index, config).await?;
+    if let Some(mtu) = config.network.mtu {
+        eprintln!("wg: setting interface mtu {mtu}");
+        link::set_link_mtu(&handle, link_index, mtu).await?;
+    }
     eprintln!("wg: configuring peers");
-    peers::configure_peers(config)?;
+    peers::configure_peers(config).await?;
     eprintln!("wg: bringing interface up");
     link::set_link_up(&handle, link_index).await?;
     eprintln!("wg: adding peer routes");
-    routes::configure_routes(&handle, link_index, config).await?;
+    let routes = routes::configure_routes(&handle, link_index, config).await?;
+
+    Ok(WgHandles {
+        name: name.clone(),
+        link_index,
+        routes,
+    })
+}
+
+/// Applies address, peer and route changes between two configurations while
+/// keeping the interface and unchanged peers' sessions in place.
+pub async fn reload(
+    current: &ResolvedConfig,
+    updated: &ResolvedConfig,
+    handle: &mut WgHandles,
+) -> Result<()> {
+    let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
+    tokio::spawn(connection);
+
+    eprintln!("wg: reloading {} addresses", handle.name);
+    addresses::reload_addresses(&netlink, handle.link_index, current, updated).await?;
+    if current.network.mtu != updated.network.mtu {
+        // Without `mtu` the link goes back to what a fresh one would have.
+        let mtu = updated.network.mtu.unwrap_or(link::DEFAULT_MTU);
+        eprintln!("wg: setting interface mtu {mtu}");
+        link::set_link_mtu(&netlink, handle.link_index, mtu).await?;
+    }
+
+    eprintln!("wg: reloading {} peers", handle.name);
+    peers::reload_peers(updated).await?;
+    eprintln!("wg: reloading {} peer routes", handle.name);
+    routes::reload_routes(&netlink, handle.link_index, &mut handle.routes, updated).await?;
+    Ok(())
+}
+
+/// Works out what `apply` would do for `config`, only reading the links.
+pub async fn plan(config: &ResolvedConfig) -> Result<InterfacePlan> {
+    let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
+    tokio::spawn(connection);
+
+    let exists = crate::netlink_util::get_link_by_name(&netlink, &config.interface)
+        .await?
+        .is_some();
+    let mut peers = Vec::new();
+    for peer in &config.peers {
+        let Some(lease) = config.lease(&peer.id) else {
+            continue;
+        };
+        peers.push(PeerPlan {
+            peer_id: peer.id.clone(),
+            allowed_ips: [lease.addresses(), peer.routed_prefixes()?]
+                .concat()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        });
+    }
+    let to_strings = |nets: Vec<IpNet>| nets.iter().map(ToString::to_string).collect();
+    Ok(InterfacePlan {
+        exists,
+        listen_port: config.server.listen_port,
+        mtu: config.network.mtu,
+        addresses: to_strings(addresses::address_list(config)?),
+        peers,
+        routes: to_strings(routes::peer_routes(config)?),
+    })
+}
+
+/// The peer routes `apply` adds for `config`.
+pub fn planned_routes(config: &ResolvedConfig) -> Result<Vec<IpNet>> {
+    routes::peer_routes(config)
+}
+
+/// Deletes `routes` and the interface named `interface`, as a previous run
+/// left them; returns whether the interface existed.
+pub async fn remove_stale(interface: &str, routes: &[IpNet]) -> Result<bool> {
+    let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
+    tokio::spawn(connection);
+
+    let Some(link) = crate::netlink_util::get_link_by_name(&netlink, interface).await? else {
+        return Ok(false);
+    };
+    teardown(WgHandles {
+        name: interface.to_string(),
+        link_index: link.header.index,
+        routes: routes.to_vec(),
+    })
+    .await?;
+    Ok(true)
+}
+
+/// Removes one peer and its routes from a running interface; does nothing if
+/// the interface is not up.
+pub async fn remove_peer(interface: &str, public_key: &str, addresses: &[String]) -> Result<bool> {
+    let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
+    tokio::spawn(connection);
+
+    let Some(link) = crate::netlink_util::get_link_by_name(&netlink, interface).await? else {
+        return Ok(false);
+    };
+    let key = crate::config::decode_key(public_key)?;
+    device::update_device(interface, None, &[device::PeerChange::Remove(key)]).await?;
+    let routes = addresses
+        .iter()
+        .map(|addr| {
+            addr.parse::<IpNet>()
+                .with_context(|| format!("parsing address {addr}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    routes::delete_routes(&netlink, link.header.index, &routes).await?;
+    Ok(true)
+}
+
+/// The peers of `interface` as the kernel reports them, or `None` if the
+/// interface does not exist.
+pub async fn peer_status(interface: &str) -> Result<Option<Vec<PeerStatus>>> {
+    let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
+    tokio::spawn(connection);
 
-    Ok(WgHandles { link_index })
+    if crate::netlink_util::get_link_by_name(&netlink, interface)
+        .await?
+        .is_none()
+    {
+        return Ok(None);
+    }
+    let info = device::get_device(interface).await?;
+    let peers = info
+        .peers
+        .into_iter()
+        .map(|peer| PeerStatus {
+            public_key: crate::config::encode_key(&peer.public_key),
+            endpoint: peer.endpoint,
+            last_handshake: peer.last_handshake,
+            rx_bytes: peer.rx_bytes,
+            tx_bytes: peer.tx_bytes,
+            persistent_keepalive: peer.persistent_keepalive,
+        })
+        .collect();
+    Ok(Some(peers))
 }
 
-pub async fn teardown(config: &ResolvedConfig, handle: WgHandles) -> Result<()> {
+pub async fn teardown(handle: WgHandles) -> Result<()> {
     let (connection, netlink, _) = rtnetlink::new_connection().context("opening netlink")?;
     tokio::spawn(connection);
 
-    peers::best_effort_wg_cleanup(config);
-    routes::delete_routes(&netlink, handle.link_index, config).await?;
+    peers::best_effort_wg_cleanup(&handle.name).await;
+    routes::delete_routes(&netlink, handle.link_index, &handle.routes).await?;
     let res = netlink.link().del(handle.link_index).execute().await;
     util::ignore_notfound(res).context("deleting wg link")?;
     Ok(())
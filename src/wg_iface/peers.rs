@@ -1,125 +1,105 @@
-use crate::config::ResolvedConfig;
-use crate::wg_iface::WG_IFACE;
+use crate::config::{ResolvedConfig, decode_key};
+use crate::wg_iface::device::{self, DeviceConfig, PeerChange, PeerConfig};
 use anyhow::{Context, Result};
+use ipnet::IpNet;
 use std::path::Path;
-use std::process::Command;
 
-pub(super) fn configure_peers(config: &ResolvedConfig) -> Result<()> {
-    let private_key_path = config.paths.keys.join("server.key");
-    let listen_port = config.server.listen_port.to_string();
-    let private_key_path = private_key_path
-        .to_str()
-        .context("server key path not utf-8")?
-        .to_string();
-    run_wg_command(&[
-        "set".to_string(),
-        WG_IFACE.to_string(),
-        "listen-port".to_string(),
-        listen_port,
-        "private-key".to_string(),
-        private_key_path,
-    ])?;
+pub(super) async fn configure_peers(config: &ResolvedConfig) -> Result<()> {
+    let private_key = decode_key(&read_to_string(config.paths.keys.join("server.key"))?)
+        .context("parsing server.key")?;
 
-    for peer in &config.peers {
-        let peer_dir = config.paths.peers.join(&peer.id);
-        let public_key = read_to_string(peer_dir.join("public.key"))?;
-        let allowed_ips = peer_allowed_ips(&peer_dir)?;
-        if allowed_ips.is_empty() {
-            continue;
+    let device_config = DeviceConfig {
+        private_key,
+        listen_port: config.server.listen_port,
+        peers: desired_peers(config)?,
+    };
+    let name = &config.interface;
+    device::set_device(name, &device_config).await?;
+
+    let info = device::get_device(name).await?;
+    for peer in &device_config.peers {
+        if !info.peers.iter().any(|p| p.public_key == peer.public_key) {
+            anyhow::bail!("wireguard device {name} is missing a configured peer after setup");
         }
-        let preshared_path = peer_dir
-            .join("preshared.key")
-            .to_str()
-            .context("preshared key path not utf-8")?
-            .to_string();
-        let allowed_list = allowed_ips.join(",");
-        run_wg_command(&[
-            "set".to_string(),
-            WG_IFACE.to_string(),
-            "peer".to_string(),
-            public_key.trim().to_string(),
-            "preshared-key".to_string(),
-            preshared_path,
-            "allowed-ips".to_string(),
-            allowed_list,
-        ])?;
     }
+    eprintln!(
+        "wg: {} peers configured, listening on port {}",
+        info.peers.len(),
+        info.listen_port
+    );
 
     Ok(())
 }
 
-pub(super) fn peer_allowed_ips(peer_dir: &Path) -> Result<Vec<String>> {
-    let conf_path = peer_dir.join("client.conf");
-    if !conf_path.exists() {
-        return Ok(Vec::new());
-    }
-    let text = std::fs::read_to_string(&conf_path)
-        .with_context(|| format!("reading {}", conf_path.display()))?;
-    Ok(extract_addresses(&text))
-}
+/// Brings the device's peers in line with `config`, touching only peers that
+/// were added, removed, or whose PSK or allowed IPs changed.
+pub(super) async fn reload_peers(config: &ResolvedConfig) -> Result<()> {
+    let info = device::get_device(&config.interface).await?;
+    let desired = desired_peers(config)?;
 
-pub(super) fn best_effort_wg_cleanup(config: &ResolvedConfig) {
-    for peer in &config.peers {
-        let peer_dir = config.paths.peers.join(&peer.id);
-        let public_key = match read_to_string(peer_dir.join("public.key")) {
-            Ok(key) => key,
-            Err(err) => {
-                eprintln!(
-                    "wg cleanup: unable to read public key for {}: {err}",
-                    peer.id
-                );
-                continue;
+    let mut changes = Vec::new();
+    for current in &info.peers {
+        if !desired.iter().any(|p| p.public_key == current.public_key) {
+            changes.push(PeerChange::Remove(current.public_key));
+        }
+    }
+    let (mut added, mut updated) = (0, 0);
+    for peer in desired {
+        match info.peers.iter().find(|p| p.public_key == peer.public_key) {
+            None => added += 1,
+            Some(current)
+                if current.preshared_key != peer.preshared_key
+                    || sorted(&current.allowed_ips) != sorted(&peer.allowed_ips) =>
+            {
+                updated += 1;
             }
-        };
-        let args = [
-            "set".to_string(),
-            WG_IFACE.to_string(),
-            "peer".to_string(),
-            public_key.trim().to_string(),
-            "remove".to_string(),
-        ];
-        if let Err(err) = run_wg_command(&args) {
-            eprintln!("wg cleanup: failed to remove peer {}: {err}", peer.id);
+            Some(_) => continue,
         }
+        changes.push(PeerChange::Upsert(peer));
     }
+    let removed = changes.len() - added - updated;
 
-    let args = [
-        "set".to_string(),
-        WG_IFACE.to_string(),
-        "listen-port".to_string(),
-        "0".to_string(),
-    ];
-    if let Err(err) = run_wg_command(&args) {
-        eprintln!("wg cleanup: failed to reset listen port: {err}");
+    let listen_port =
+        (info.listen_port != config.server.listen_port).then_some(config.server.listen_port);
+    if changes.is_empty() && listen_port.is_none() {
+        eprintln!("wg: peers unchanged");
+        return Ok(());
     }
+    device::update_device(&config.interface, listen_port, &changes).await?;
+    eprintln!("wg: peers reloaded ({added} added, {updated} updated, {removed} removed)");
+    Ok(())
 }
 
-fn extract_addresses(text: &str) -> Vec<String> {
-    for line in text.lines() {
-        let line = line.trim();
-        if let Some(rest) = line.strip_prefix("Address") {
-            let parts: Vec<&str> = rest.split('=').collect();
-            if parts.len() == 2 {
-                return parts[1]
-                    .split(',')
-                    .map(|item| item.trim().to_string())
-                    .filter(|item| !item.is_empty())
-                    .collect();
-            }
-        }
+fn desired_peers(config: &ResolvedConfig) -> Result<Vec<PeerConfig>> {
+    let mut peers = Vec::new();
+    for peer in &config.peers {
+        let Some(lease) = config.lease(&peer.id) else {
+            continue;
+        };
+        let peer_dir = config.paths.peers.join(&peer.id);
+        let public_key = decode_key(&lease.public_key)
+            .with_context(|| format!("parsing leased public key for {}", peer.id))?;
+        let preshared_key = decode_key(&read_to_string(peer_dir.join("preshared.key"))?)
+            .with_context(|| format!("parsing preshared.key for {}", peer.id))?;
+        peers.push(PeerConfig {
+            public_key,
+            preshared_key: Some(preshared_key),
+            allowed_ips: [lease.addresses(), peer.routed_prefixes()?].concat(),
+        });
     }
-    Vec::new()
+    Ok(peers)
 }
 
-fn run_wg_command(args: &[String]) -> Result<()> {
-    let status = Command::new("wg")
-        .args(args)
-        .status()
-        .with_context(|| format!("running wg {}", args.join(" ")))?;
-    if !status.success() {
-        anyhow::bail!("wg command failed");
+fn sorted(ips: &[IpNet]) -> Vec<IpNet> {
+    let mut ips = ips.to_vec();
+    ips.sort();
+    ips
+}
+
+pub(super) async fn best_effort_wg_cleanup(name: &str) {
+    if let Err(err) = device::clear_device(name).await {
+        eprintln!("wg cleanup: {name}: failed to remove peers and reset listen port: {err}");
     }
-    Ok(())
 }
 
 fn read_to_string<P: AsRef<Path>>(path: P) -> Result<String> {
@@ -1,77 +1,98 @@
 use crate::config::ResolvedConfig;
-use crate::wg_iface::peers::peer_allowed_ips;
 use crate::wg_iface::util::{ignore_exists, ignore_notfound};
 use anyhow::{Context, Result};
 use ipnet::IpNet;
+use netlink_packet_route::route::RouteMessage;
 use rtnetlink::RouteMessageBuilder;
 
 pub(super) async fn configure_routes(
     handle: &rtnetlink::Handle,
     link_index: u32,
     config: &ResolvedConfig,
+) -> Result<Vec<IpNet>> {
+    let routes = peer_routes(config)?;
+    for route in &routes {
+        add_route(handle, link_index, *route).await?;
+    }
+    Ok(routes)
+}
+
+pub(super) async fn delete_routes(
+    handle: &rtnetlink::Handle,
+    link_index: u32,
+    routes: &[IpNet],
 ) -> Result<()> {
-    for peer in &config.peers {
-        let peer_dir = config.paths.peers.join(&peer.id);
-        for allowed in peer_allowed_ips(&peer_dir)? {
-            let ipnet: IpNet = allowed
-                .parse()
-                .with_context(|| format!("parsing allowed ip {allowed}"))?;
-            let res = match ipnet {
-                IpNet::V4(v4) => {
-                    handle
-                        .route()
-                        .add(
-                            RouteMessageBuilder::<std::net::Ipv4Addr>::new()
-                                .output_interface(link_index)
-                                .destination_prefix(v4.addr(), v4.prefix_len())
-                                .build(),
-                        )
-                        .execute()
-                        .await
-                }
-                IpNet::V6(v6) => {
-                    handle
-                        .route()
-                        .add(
-                            RouteMessageBuilder::<std::net::Ipv6Addr>::new()
-                                .output_interface(link_index)
-                                .destination_prefix(v6.addr(), v6.prefix_len())
-                                .build(),
-                        )
-                        .execute()
-                        .await
-                }
-            };
-            ignore_exists(res).with_context(|| format!("adding route {allowed}"))?;
-        }
+    for route in routes {
+        delete_route(handle, link_index, *route).await?;
     }
     Ok(())
 }
 
-pub(super) async fn delete_routes(
+/// Adds routes for new peers and removes routes of peers that went away,
+/// leaving routes shared by both configurations untouched. `routes` follows
+/// every route added or deleted, so it stays accurate if one of them fails.
+pub(super) async fn reload_routes(
     handle: &rtnetlink::Handle,
     link_index: u32,
-    config: &ResolvedConfig,
+    routes: &mut Vec<IpNet>,
+    updated: &ResolvedConfig,
 ) -> Result<()> {
-    for peer in &config.peers {
-        let peer_dir = config.paths.peers.join(&peer.id);
-        for allowed in peer_allowed_ips(&peer_dir)? {
-            let ipnet: IpNet = allowed
-                .parse()
-                .with_context(|| format!("parsing allowed ip {allowed}"))?;
-            let message = match ipnet {
-                IpNet::V4(v4) => RouteMessageBuilder::<std::net::Ipv4Addr>::new()
-                    .output_interface(link_index)
-                    .destination_prefix(v4.addr(), v4.prefix_len())
-                    .build(),
-                IpNet::V6(v6) => RouteMessageBuilder::<std::net::Ipv6Addr>::new()
-                    .output_interface(link_index)
-                    .destination_prefix(v6.addr(), v6.prefix_len())
-                    .build(),
-            };
-            let res = handle.route().del(message).execute().await;
-            ignore_notfound(res).with_context(|| format!("deleting route {allowed}"))?;
+    let after = peer_routes(updated)?;
+    while let Some(idx) = routes.iter().position(|route| !after.contains(route)) {
+        delete_route(handle, link_index, routes[idx]).await?;
+        routes.remove(idx);
+    }
+    for route in after {
+        if !routes.contains(&route) {
+            add_route(handle, link_index, route).await?;
+            routes.push(route);
         }
     }
     Ok(())
 }
+
+/// Host routes for each peer unless the server address already carries the
+/// subnet prefix, plus any prefixes routed behind a peer.
+pub(super) fn peer_routes(config: &ResolvedConfig) -> Result<Vec<IpNet>> {
+    let mut routes = Vec::new();
+    for peer in &config.peers {
+        if !config.network.assign_subnet_prefix
+            && let Some(lease) = config.lease(&peer.id)
+        {
+            routes.extend(lease.addresses());
+        }
+        routes.extend(peer.routed_prefixes()?);
+    }
+    Ok(routes)
+}
+
+async fn add_route(handle: &rtnetlink::Handle, link_index: u32, route: IpNet) -> Result<()> {
+    let res = handle
+        .route()
+        .add(route_message(link_index, route))
+        .execute()
+        .await;
+    ignore_exists(res).with_context(|| format!("adding route {route}"))
+}
+
+async fn delete_route(handle: &rtnetlink::Handle, link_index: u32, route: IpNet) -> Result<()> {
+    let res = handle
+        .route()
+        .del(route_message(link_index, route))
+        .execute()
+        .await;
+    ignore_notfound(res).with_context(|| format!("deleting route {route}"))
+}
+
+fn route_message(link_index: u32, route: IpNet) -> RouteMessage {
+    match route {
+        IpNet::V4(v4) => RouteMessageBuilder::<std::net::Ipv4Addr>::new()
+            .output_interface(link_index)
+            .destination_prefix(v4.addr(), v4.prefix_len())
+            .build(),
+        IpNet::V6(v6) => RouteMessageBuilder::<std::net::Ipv6Addr>::new()
+            .output_interface(link_index)
+            .destination_prefix(v6.addr(), v6.prefix_len())
+            .build(),
+    }
+}